@@ -1,52 +1,405 @@
+use anyhow::Context;
 use clap::Parser;
-use std::{fs::File, path::PathBuf};
+use patcher_common::{
+    determinants::select_determinants,
+    error::GlobalErrorType,
+    hash::{HashAlgorithm, hash_file},
+    structures::source::{Determinants, Source},
+};
+use serde_yaml::{Mapping, Value};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+use thl_patcher::ComposeState;
 use walkdir::WalkDir;
 use xz2::{read::XzDecoder, write::XzEncoder};
 
 const COMPRESSION_LEVEL: u32 = 9;
 
-#[derive(clap::ValueEnum, Clone)]
+#[derive(clap::Subcommand, Clone)]
 pub enum Command {
-    Diff,
-    Patch,
+    /// Generates the patch going from `old` to `new`
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        destination: PathBuf,
+        /// Also writes the reverse patch (from new to old) at this path
+        #[arg(long)]
+        reverse: Option<PathBuf>,
+    },
+    /// Applies the patch `new` on `old`, writing the patched files in `destination`
+    Patch {
+        old: PathBuf,
+        new: PathBuf,
+        destination: PathBuf,
+    },
+    /// Generates the patch going from the last version of `source` to a new build, and appends
+    /// that build to `source`
+    Release(ReleaseArgs),
+    /// Chooses files identifying each build, given from the oldest to the newest
+    Determinants {
+        #[arg(required = true)]
+        builds: Vec<PathBuf>,
+        /// The algorithm the files are hashed with
+        #[arg(long, default_value = "sha256")]
+        hash: HashAlgorithm,
+    },
+    /// Writes the manifest listing every file of `build`
+    Manifest {
+        build: PathBuf,
+        destination: PathBuf,
+        /// The algorithm the files are hashed with
+        #[arg(long, default_value = "sha256")]
+        hash: HashAlgorithm,
+        /// The URL the files of the build will be hosted at, allowing to download them one by one
+        #[arg(long)]
+        files_link: Option<String>,
+    },
+    /// Merges a sequence of patches, starting from `base`, into a single cumulative patch
+    Compose {
+        base: PathBuf,
+        destination: PathBuf,
+        /// The patches to merge, in the order they would be applied
+        #[arg(required = true)]
+        patches: Vec<PathBuf>,
+    },
+}
+
+#[derive(clap::Args, Clone)]
+pub struct ReleaseArgs {
+    /// The `source.yaml` to update
+    source: PathBuf,
+    /// The build of the last version of `source`
+    old: PathBuf,
+    /// The build of the new version
+    new: PathBuf,
+    /// Where to write the patch archive
+    destination: PathBuf,
+    /// The name of the new version
+    #[arg(long)]
+    name: String,
+    /// The URL the patch archive will be hosted at
+    #[arg(long)]
+    link: String,
+    /// Files identifying the new version, defaults to the determinants of the last version
+    #[arg(long = "determinant")]
+    determinants: Vec<String>,
+    /// The algorithm the determinants are hashed with
+    #[arg(long, default_value = "sha256")]
+    hash: HashAlgorithm,
 }
 
 #[derive(clap::Parser, Clone)]
 pub struct Argument {
+    #[command(subcommand)]
     pub subcommand: Command,
-    pub old: PathBuf,
-    pub new: PathBuf,
-    pub destination: PathBuf,
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Argument::parse();
-    match args.subcommand {
-        Command::Diff => {
-            let count = WalkDir::new(&args.new)
-                .into_iter()
-                .filter(|entry| entry.as_ref().is_ok_and(|f| f.file_type().is_file()))
-                .count();
-            let progress_bar = indicatif::ProgressBar::new(count as u64);
-            thl_patcher::diff_in_tar(
-                &args.old,
-                &args.new,
-                &mut tar::Builder::new(XzEncoder::new(
-                    File::create(args.destination)?,
-                    COMPRESSION_LEVEL,
-                )),
+fn build_files(dir: &Path) -> impl Iterator<Item = walkdir::DirEntry> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+}
+
+fn diff(old: &Path, new: &Path, destination: &Path, reverse: Option<&Path>) -> anyhow::Result<()> {
+    let progress_bar = indicatif::ProgressBar::new(build_files(new).count() as u64);
+    let mut destination = tar::Builder::new(XzEncoder::new(
+        File::create(destination)?,
+        COMPRESSION_LEVEL,
+    ));
+    match reverse {
+        Some(reverse) => {
+            let mut reverse =
+                tar::Builder::new(XzEncoder::new(File::create(reverse)?, COMPRESSION_LEVEL));
+            thl_patcher::diff_in_tar_with_reverse(
+                old,
+                new,
+                &mut destination,
+                &mut reverse,
+                &thl_patcher::CancellationToken::new(),
                 |_| progress_bar.inc(1),
             )?;
+            reverse.into_inner()?.finish()?;
         }
-        Command::Patch => {
+        None => thl_patcher::diff_in_tar(
+            old,
+            new,
+            &mut destination,
+            &thl_patcher::CancellationToken::new(),
+            |_| progress_bar.inc(1),
+        )?,
+    }
+    destination.into_inner()?.finish()?;
+    Ok(())
+}
+
+///
+/// Appends the build `new` to `source`, patched from its last version by the archive
+/// `destination`. The file is rewritten, so comments of the original are lost.
+///
+fn release(
+    ReleaseArgs {
+        source: source_path,
+        old,
+        new,
+        destination,
+        name,
+        link,
+        determinants,
+        hash,
+    }: ReleaseArgs,
+) -> anyhow::Result<()> {
+    let mut document: serde_yaml::Value =
+        serde_yaml::from_str(&std::fs::read_to_string(&source_path)?)?;
+    let source: Source = serde_yaml::from_value(document.clone())?;
+    let last = source
+        .versions
+        .len()
+        .checked_sub(1)
+        .context("the source has no version to update from")?;
+    let previous = &source.versions[last];
+    anyhow::ensure!(
+        source.find_version(&name).is_none(),
+        "version `{name}` already exists"
+    );
+    anyhow::ensure!(
+        source.get_current_version(&old)? == Some(last),
+        "`{}` is not a build of version `{}`",
+        old.display(),
+        previous.name
+    );
+
+    let files = if determinants.is_empty() {
+        previous
+            .determinants
+            .iter()
+            .map(|x| x.file.clone())
+            .collect()
+    } else {
+        determinants
+    };
+    let mut determinants = Vec::with_capacity(files.len());
+    for file in files {
+        // Files missing from the new build identify it by their absence
+        let determinant = match File::open(new.join(&file)) {
+            Ok(f) => {
+                let mut determinant = Determinants {
+                    size: Some(f.metadata()?.len()),
+                    ..Default::default()
+                };
+                determinant.set_hash(hash, hash_file(f, hash)?);
+                determinant.file = file;
+                determinant
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Determinants {
+                file,
+                absent: true,
+                ..Default::default()
+            },
+            Err(e) => Err(e).with_context(|| format!("cannot open `{file}`"))?,
+        };
+        determinants.push(determinant);
+    }
+    anyhow::ensure!(
+        !determinants.is_empty(),
+        "the new version has no determinants"
+    );
+    if matches_build(&determinants, &old) {
+        eprintln!(
+            "warning: the determinants of `{name}` also match the build of `{}`, try `patcher-cli determinants`",
+            previous.name
+        );
+    }
+
+    diff(&old, &new, &destination, None)?;
+    let archive_size = std::fs::metadata(&destination)?.len();
+    let mut unpacked_size = 0;
+    for entry in build_files(&new) {
+        unpacked_size += entry.metadata()?.len();
+    }
+
+    let versions = document
+        .get_mut("versions")
+        .and_then(Value::as_sequence_mut)
+        .context("the source has no versions")?;
+    let previous = versions
+        .last_mut()
+        .and_then(Value::as_mapping_mut)
+        .context("invalid last version")?;
+    previous.insert("update_link".into(), link.into());
+    previous.insert("archive_size".into(), archive_size.into());
+    previous.insert("unpacked_size".into(), unpacked_size.into());
+    let mut version = Mapping::new();
+    version.insert("name".into(), name.into());
+    version.insert("determinants".into(), determinants_to_value(&determinants));
+    versions.push(Value::Mapping(version));
+
+    let source: Source = serde_yaml::from_value(document.clone())?;
+    let errors = source.validate();
+    if !errors.is_empty() {
+        anyhow::bail!(GlobalErrorType::InvalidSource(errors));
+    }
+    std::fs::write(&source_path, serde_yaml::to_string(&document)?)?;
+    Ok(())
+}
+
+fn determinants_to_value(determinants: &[Determinants]) -> Value {
+    Value::Sequence(
+        determinants
+            .iter()
+            .map(|determinant| {
+                let mut mapping = Mapping::new();
+                mapping.insert("file".into(), determinant.file.clone().into());
+                for (algorithm, hash) in determinant.hashes() {
+                    mapping.insert(algorithm.name().into(), hash.into());
+                }
+                if let Some(size) = determinant.size {
+                    mapping.insert("size".into(), size.into());
+                }
+                if determinant.absent {
+                    mapping.insert("absent".into(), true.into());
+                }
+                Value::Mapping(mapping)
+            })
+            .collect(),
+    )
+}
+
+///
+/// Whether all the `determinants` match the files of `build`
+///
+fn matches_build(determinants: &[Determinants], build: &Path) -> bool {
+    determinants
+        .iter()
+        .all(|determinant| determinant.matches(build).unwrap_or(false))
+}
+
+///
+/// Prints the determinants identifying each of the `builds`, from the oldest to the newest
+///
+fn determinants(builds: &[PathBuf], hash: HashAlgorithm) -> anyhow::Result<()> {
+    let selection = select_determinants(builds, hash)?;
+    for (j, k) in selection.indistinguishable {
+        eprintln!(
+            "warning: `{}` cannot be told apart from `{}`",
+            builds[k].display(),
+            builds[j].display()
+        );
+    }
+    let document = builds
+        .iter()
+        .zip(&selection.determinants)
+        .map(|(build, determinants)| {
+            let mut mapping = Mapping::new();
+            mapping.insert("build".into(), build.display().to_string().into());
+            mapping.insert("determinants".into(), determinants_to_value(determinants));
+            Value::Mapping(mapping)
+        })
+        .collect();
+    print!("{}", serde_yaml::to_string(&Value::Sequence(document))?);
+    Ok(())
+}
+
+///
+/// Writes the manifest of `build` in `destination`, with the hash and size of all its files
+///
+fn manifest(
+    build: &Path,
+    destination: &Path,
+    hash: HashAlgorithm,
+    files_link: Option<String>,
+) -> anyhow::Result<()> {
+    let mut files = Vec::new();
+    for entry in build_files(build) {
+        let file = entry
+            .path()
+            .strip_prefix(build)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let mut determinant = Determinants {
+            file,
+            size: Some(entry.metadata()?.len()),
+            ..Default::default()
+        };
+        determinant.set_hash(hash, hash_file(File::open(entry.path())?, hash)?);
+        files.push(determinant);
+    }
+    files.sort_by(|a, b| a.file.cmp(&b.file));
+
+    let mut document = Mapping::new();
+    document.insert("files".into(), determinants_to_value(&files));
+    if let Some(files_link) = files_link {
+        document.insert("files_link".into(), files_link.into());
+    }
+    std::fs::write(destination, serde_yaml::to_string(&document)?)?;
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Argument::parse();
+    match args.subcommand {
+        Command::Diff {
+            old,
+            new,
+            destination,
+            reverse,
+        } => diff(&old, &new, &destination, reverse.as_deref())?,
+        Command::Release(args) => release(args)?,
+        Command::Determinants { builds, hash } => determinants(&builds, hash)?,
+        Command::Manifest {
+            build,
+            destination,
+            hash,
+            files_link,
+        } => manifest(&build, &destination, hash, files_link)?,
+        Command::Patch {
+            old,
+            new,
+            destination,
+        } => {
             let progress_bar = indicatif::ProgressBar::new_spinner();
             thl_patcher::patch_from_tar(
-                &args.old,
-                &mut tar::Archive::new(XzDecoder::new(File::open(args.new)?)),
-                &args.destination,
+                &old,
+                &mut tar::Archive::new(XzDecoder::new(File::open(new)?)),
+                &destination,
+                &thl_patcher::CancellationToken::new(),
                 |current| progress_bar.set_message(format!("patching {}", current.path.display())),
             )?;
         }
+        Command::Compose {
+            base,
+            destination,
+            patches,
+        } => {
+            let archives = patches
+                .iter()
+                .map(|path| Ok(tar::Archive::new(XzDecoder::new(File::open(path)?))))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            let progress_bar = indicatif::ProgressBar::new_spinner();
+            let mut destination = tar::Builder::new(XzEncoder::new(
+                File::create(destination)?,
+                COMPRESSION_LEVEL,
+            ));
+            thl_patcher::compose_in_tar(
+                &base,
+                archives,
+                &mut destination,
+                &thl_patcher::CancellationToken::new(),
+                |state| match state {
+                    ComposeState::Patching { archive, path } => progress_bar.set_message(format!(
+                        "applying {} from {}",
+                        path.display(),
+                        patches[archive].display()
+                    )),
+                    ComposeState::Diffing(state) => {
+                        progress_bar.set_message(format!("diffing {}/{}", state.done, state.out_of))
+                    }
+                },
+            )?;
+            destination.into_inner()?.finish()?;
+        }
     }
     anyhow::Ok(())
 }
@@ -0,0 +1,411 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    download::PATCHER_DIR,
+    error::RollbackError,
+    history::{self, HistoryEntry, Outcome},
+    lock::InstallLock,
+    state::InstalledState,
+    structures::source::{Source, VersionTransitionRef},
+};
+
+const BACKUPS_DIR: &str = "backups";
+const BACKUP_FILE: &str = "backup.yaml";
+const FILES_DIR: &str = "files";
+/// Where the files replaced by a rollback wait until the restored version is checked
+const REPLACED_DIR: &str = "replaced";
+/// Number of backups kept for the main versions and for each component
+pub const MAX_BACKUPS: usize = 5;
+
+///
+/// The files replaced by a single transition, as they were before the update
+///
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Backup {
+    pub from: String,
+    pub to: String,
+    /// Files of the install directory saved in this backup
+    pub files: Vec<PathBuf>,
+    /// The component updated by the transition, `None` for the main versions
+    #[serde(default)]
+    pub component: Option<String>,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+fn backups_dir(original: &Path) -> PathBuf {
+    original.join(PATCHER_DIR).join(BACKUPS_DIR)
+}
+
+impl Backup {
+    ///
+    /// Creates an empty backup for `transition`, in a directory numbered after the last backup
+    ///
+    pub(crate) fn create(original: &Path, transition: &VersionTransitionRef) -> io::Result<Self> {
+        let backups_dir = backups_dir(original);
+        std::fs::create_dir_all(&backups_dir)?;
+        let mut last = 0;
+        for entry in std::fs::read_dir(&backups_dir)? {
+            if let Ok(x) = entry?.file_name().to_string_lossy().parse::<u32>() {
+                last = last.max(x);
+            }
+        }
+        let dir = backups_dir.join(format!("{:06}", last + 1));
+        std::fs::create_dir(&dir)?;
+        Ok(Self {
+            from: transition.old.name.clone(),
+            to: transition.new.name.clone(),
+            files: Vec::new(),
+            component: transition.new.component.clone(),
+            dir,
+        })
+    }
+
+    ///
+    /// Moves the file `suffix` of the install directory into the backup
+    ///
+    pub(crate) fn store(&mut self, original: &Path, suffix: &Path) -> io::Result<()> {
+        let destination = self.dir.join(FILES_DIR).join(suffix);
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(original.join(suffix), destination)?;
+        self.files.push(suffix.to_path_buf());
+        Ok(())
+    }
+
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let content = serde_yaml::to_string(self).map_err(io::Error::other)?;
+        std::fs::write(self.dir.join(BACKUP_FILE), content)
+    }
+
+    ///
+    /// Lists the backups of the install directory `original`, from the oldest to the newest
+    ///
+    pub fn list(original: &Path) -> Result<Vec<Self>, RollbackError> {
+        let backups_dir = backups_dir(original);
+        if !backups_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut dirs = Vec::new();
+        for entry in std::fs::read_dir(backups_dir)? {
+            let entry = entry?;
+            if entry.path().join(BACKUP_FILE).is_file() {
+                dirs.push(entry.path());
+            }
+        }
+        dirs.sort();
+
+        let mut backups = Vec::with_capacity(dirs.len());
+        for dir in dirs {
+            let content = std::fs::read_to_string(dir.join(BACKUP_FILE))?;
+            let mut backup: Self = serde_yaml::from_str(&content)?;
+            backup.dir = dir;
+            backups.push(backup);
+        }
+        Ok(backups)
+    }
+
+    ///
+    /// Removes the oldest backups of `original` made while updating `component`, keeping the
+    /// [`MAX_BACKUPS`] most recent ones
+    ///
+    pub(crate) fn prune(original: &Path, component: Option<&str>) -> Result<(), RollbackError> {
+        let backups = Self::list(original)?;
+        for backup in backups
+            .iter()
+            .rev()
+            .filter(|backup| backup.component.as_deref() == component)
+            .skip(MAX_BACKUPS)
+        {
+            log::info!("removing backup of `{}` to `{}`", backup.from, backup.to);
+            std::fs::remove_dir_all(&backup.dir)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Gets the most recent backup of `original` made while updating `component`
+    ///
+    pub fn last(original: &Path, component: Option<&str>) -> Result<Option<Self>, RollbackError> {
+        Ok(Self::list(original)?
+            .into_iter()
+            .rev()
+            .find(|backup| backup.component.as_deref() == component))
+    }
+}
+
+///
+/// Restores the most recent backup of `original` for the component of `source`, bringing it back to the version it had before
+/// the last update, and returns the index of that version in `source`.
+///
+/// The restored version is checked with [`Source::get_current_version`], and the rollback is
+/// recorded in the history like any other transition.
+///
+pub fn rollback(original: &Path, source: &Source) -> Result<usize, RollbackError> {
+    let _lock = InstallLock::acquire(original)?;
+    let backup = Backup::last(original, source.component_name())?.ok_or(RollbackError::NoBackup)?;
+
+    if let Some(current) = source.get_current_version(original)? {
+        let found = &source.versions[current].name;
+        if *found != backup.to {
+            return Err(RollbackError::VersionMismatch {
+                expected: backup.to,
+                found: found.clone(),
+            });
+        }
+    }
+
+    let mut entry = HistoryEntry {
+        timestamp: Utc::now(),
+        from: backup.to.clone(),
+        to: backup.from.clone(),
+        archive_sha256: None,
+        files: Vec::new(),
+        skipped: Vec::new(),
+        outcome: Outcome::Success,
+        error: None,
+        component: backup.component.clone(),
+    };
+    let res = restore(original, source, &backup, &mut entry);
+    if let Err(e) = &res {
+        entry.outcome = Outcome::Failed;
+        entry.error = Some(e.to_string());
+    }
+    if let Err(e) = history::append(original, &entry) {
+        log::error!("couldn't write the update history: {e}");
+    }
+    res
+}
+
+///
+/// Moves the files of `backup` back into `original`, and checks that they make up the version
+/// the backup was made from. The files they replace are kept in the backup until then, so that
+/// a failed verification leaves both the install and the backup as they were.
+///
+fn restore(
+    original: &Path,
+    source: &Source,
+    backup: &Backup,
+    entry: &mut HistoryEntry,
+) -> Result<usize, RollbackError> {
+    InstalledState::remove(original, backup.component.as_deref())?;
+    let files_dir = backup.dir.join(FILES_DIR);
+    let replaced_dir = backup.dir.join(REPLACED_DIR);
+    let mut restored = Vec::new();
+    let res = (|| {
+        for file in &backup.files {
+            let destination = original.join(file);
+            if destination.exists() {
+                move_file(&destination, &replaced_dir.join(file))?;
+            }
+            move_file(&files_dir.join(file), &destination)?;
+            restored.push(file);
+        }
+        match source.get_current_version(original)? {
+            Some(i) if source.versions[i].name == backup.from => Ok(i),
+            _ => Err(RollbackError::VerificationFailed(backup.from.clone())),
+        }
+    })();
+
+    match res {
+        Ok(i) => {
+            entry.files = backup.files.clone();
+            std::fs::remove_dir_all(&backup.dir)?;
+            if let Err(e) = InstalledState::write(original, &source.versions[i]) {
+                log::error!("couldn't write the state file: {e}");
+            }
+            Ok(i)
+        }
+        Err(e) => {
+            for file in restored.into_iter().rev() {
+                let undone =
+                    move_file(&original.join(file), &files_dir.join(file)).and_then(|()| {
+                        let replaced = replaced_dir.join(file);
+                        if replaced.exists() {
+                            move_file(&replaced, &original.join(file))?;
+                        }
+                        Ok(())
+                    });
+                if let Err(e) = undone {
+                    log::error!("couldn't put back `{}`: {e}", file.display());
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+///
+/// Renames `from` to `to`, creating the parent directories of `to`
+///
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(from, to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "
+versions:
+  - name: v0
+    update_link: v0-v1
+    determinants:
+      - file: a
+        size: 1
+  - name: v1
+    determinants:
+      - file: a
+        size: 2
+components:
+  - name: voices
+    versions:
+      - name: v0
+        update_link: voices-v0-v1
+        determinants:
+          - file: b
+      - name: v1
+        determinants:
+          - file: c
+";
+
+    fn source() -> Source {
+        serde_yaml::from_str(SOURCE).unwrap()
+    }
+
+    fn transition(source: &Source) -> VersionTransitionRef<'_> {
+        VersionTransitionRef {
+            old: &source.versions[0],
+            new: &source.versions[1],
+            patch: None,
+        }
+    }
+
+    ///
+    /// Installs `v1` in `original`, with a backup of the file `a` of `v0` holding `backed_up`
+    ///
+    fn updated_install(original: &Path, source: &Source, backed_up: &str) {
+        std::fs::write(original.join("a"), backed_up).unwrap();
+        let mut backup = Backup::create(original, &transition(source)).unwrap();
+        backup.store(original, Path::new("a")).unwrap();
+        backup.save().unwrap();
+        std::fs::write(original.join("a"), "11").unwrap();
+    }
+
+    #[test]
+    fn backups_are_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source();
+        std::fs::write(dir.path().join("a"), "0").unwrap();
+        let mut first = Backup::create(dir.path(), &transition(&source)).unwrap();
+        first.store(dir.path(), Path::new("a")).unwrap();
+        first.save().unwrap();
+        Backup::create(dir.path(), &transition(&source))
+            .unwrap()
+            .save()
+            .unwrap();
+
+        assert!(!dir.path().join("a").exists());
+        let backups = Backup::list(dir.path()).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert!(backups[0].dir.ends_with("000001"));
+        assert!(backups[1].dir.ends_with("000002"));
+        assert_eq!(backups[0].files, [PathBuf::from("a")]);
+        assert!(backups[1].files.is_empty());
+        assert_eq!(
+            (backups[0].from.as_str(), backups[0].to.as_str()),
+            ("v0", "v1")
+        );
+    }
+
+    #[test]
+    fn prune_keeps_the_last_backups_of_each_component() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source();
+        let component = source.component("voices").unwrap();
+        for _ in 0..MAX_BACKUPS + 2 {
+            Backup::create(dir.path(), &transition(&source))
+                .unwrap()
+                .save()
+                .unwrap();
+        }
+        for _ in 0..2 {
+            Backup::create(dir.path(), &transition(&component))
+                .unwrap()
+                .save()
+                .unwrap();
+        }
+
+        Backup::prune(dir.path(), None).unwrap();
+        let backups = Backup::list(dir.path()).unwrap();
+        let main: Vec<_> = backups.iter().filter(|x| x.component.is_none()).collect();
+        assert_eq!(main.len(), MAX_BACKUPS);
+        // The oldest ones are removed
+        assert!(main[0].dir.ends_with("000003"));
+        assert_eq!(backups.len() - main.len(), 2);
+    }
+
+    #[test]
+    fn rollback_restores_the_previous_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source();
+        updated_install(dir.path(), &source, "0");
+
+        assert_eq!(rollback(dir.path(), &source).unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(dir.path().join("a")).unwrap(), "0");
+        assert!(Backup::list(dir.path()).unwrap().is_empty());
+        let history = history::read(dir.path()).unwrap();
+        assert_eq!(
+            (history[0].from.as_str(), history[0].to.as_str()),
+            ("v1", "v0")
+        );
+        assert_eq!(history[0].outcome, Outcome::Success);
+    }
+
+    #[test]
+    fn rollback_checks_the_installed_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source();
+        updated_install(dir.path(), &source, "0");
+        std::fs::write(dir.path().join("a"), "0").unwrap();
+
+        assert!(matches!(
+            rollback(dir.path(), &source),
+            Err(RollbackError::VersionMismatch { expected, found })
+                if expected == "v1" && found == "v0"
+        ));
+        assert_eq!(Backup::list(dir.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failed_verification_keeps_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = source();
+        // The backed up file doesn't match `v0`
+        updated_install(dir.path(), &source, "000");
+
+        assert!(matches!(
+            rollback(dir.path(), &source),
+            Err(RollbackError::VerificationFailed(version)) if version == "v0"
+        ));
+        assert_eq!(std::fs::read_to_string(dir.path().join("a")).unwrap(), "11");
+        let backups = Backup::list(dir.path()).unwrap();
+        assert_eq!(backups.len(), 1);
+        let backed_up = backups[0].dir.join(FILES_DIR).join("a");
+        assert_eq!(std::fs::read_to_string(backed_up).unwrap(), "000");
+        assert_eq!(
+            history::read(dir.path()).unwrap()[0].outcome,
+            Outcome::Failed
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+use crate::{
+    download::PATCHER_DIR,
+    hash::{HashAlgorithm, hash_file},
+    structures::source::Determinants,
+};
+
+///
+/// The determinants chosen for a list of builds, see [`select_determinants`]
+///
+#[derive(Debug, Clone)]
+pub struct Selection {
+    /// For each build, the determinants identifying it
+    pub determinants: Vec<Vec<Determinants>>,
+    /// Pairs of builds that no file tells apart, the earliest one first
+    pub indistinguishable: Vec<(usize, usize)>,
+}
+
+///
+/// What a file looks like in each build: its hash and size, or `None` where it's missing
+///
+struct Candidate {
+    hashes: Vec<Option<(String, u64)>>,
+    size: u64,
+}
+
+impl Candidate {
+    ///
+    /// Whether this file, as a determinant of the build `k`, rejects the build `j`. A file
+    /// missing from `k` is still a determinant, requiring it to be absent.
+    ///
+    fn distinguishes(&self, (j, k): (usize, usize)) -> bool {
+        self.hashes[j] != self.hashes[k]
+    }
+}
+
+///
+/// Chooses a small set of files identifying each of the `builds`, given from the oldest to the
+/// newest, hashed with `algorithm`.
+///
+/// Versions are detected from the newest to the oldest, so the determinants of each build must
+/// reject every earlier build. Files are picked greedily, the one telling apart the most builds
+/// first, the smallest one on ties as it's cheaper to hash.
+///
+pub fn select_determinants(
+    builds: &[impl AsRef<Path>],
+    algorithm: HashAlgorithm,
+) -> io::Result<Selection> {
+    let mut candidates: BTreeMap<PathBuf, Candidate> = BTreeMap::new();
+    for (i, build) in builds.iter().enumerate() {
+        let build = build.as_ref();
+        for entry in WalkDir::new(build)
+            .into_iter()
+            .filter_entry(|entry| entry.file_name() != PATCHER_DIR)
+        {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry
+                .path()
+                .strip_prefix(build)
+                .map_err(io::Error::other)?
+                .to_path_buf();
+            let hash = hash_file(File::open(entry.path())?, algorithm)?;
+            let size = entry.metadata()?.len();
+            let candidate = candidates.entry(relative).or_insert_with(|| Candidate {
+                hashes: vec![None; builds.len()],
+                size: 0,
+            });
+            candidate.hashes[i] = Some((hash, size));
+            candidate.size = candidate.size.max(size);
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize)> = (0..builds.len())
+        .flat_map(|k| (0..k).map(move |j| (j, k)))
+        .collect();
+    let mut chosen: Vec<&PathBuf> = Vec::new();
+    loop {
+        let best = candidates
+            .iter()
+            .filter(|(file, _)| !chosen.contains(file))
+            .map(|(file, candidate)| {
+                let count = pairs
+                    .iter()
+                    .filter(|&&x| candidate.distinguishes(x))
+                    .count();
+                (file, candidate, count)
+            })
+            .max_by_key(|&(_, candidate, count)| (count, Reverse(candidate.size)));
+        let Some((file, candidate, count)) = best else {
+            break;
+        };
+        if count == 0 {
+            break;
+        }
+        pairs.retain(|&x| !candidate.distinguishes(x));
+        chosen.push(file);
+    }
+
+    // Builds need at least one determinant, even if they don't have to reject anything
+    if chosen.is_empty()
+        && let Some((file, _)) = candidates
+            .iter()
+            .min_by_key(|(_, candidate)| candidate.size)
+    {
+        chosen.push(file);
+    }
+    chosen.sort();
+
+    let determinants = (0..builds.len())
+        .map(|i| {
+            chosen
+                .iter()
+                .map(|file| {
+                    let file_name = file.to_string_lossy().replace('\\', "/");
+                    match &candidates[*file].hashes[i] {
+                        Some((hash, size)) => {
+                            let mut determinant = Determinants {
+                                file: file_name,
+                                size: Some(*size),
+                                ..Default::default()
+                            };
+                            determinant.set_hash(algorithm, hash.clone());
+                            determinant
+                        }
+                        None => Determinants {
+                            file: file_name,
+                            absent: true,
+                            ..Default::default()
+                        },
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    Ok(Selection {
+        determinants,
+        indistinguishable: pairs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{TempDir, tempdir};
+
+    use super::*;
+
+    fn build(files: &[(&str, &str)]) -> TempDir {
+        let dir = tempdir().unwrap();
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn files(determinants: &[Determinants]) -> Vec<&str> {
+        determinants.iter().map(|x| x.file.as_str()).collect()
+    }
+
+    #[test]
+    fn single_build_gets_its_smallest_file() {
+        let builds = [build(&[("big", "0123456789"), ("small", "0")])];
+        let selection = select_determinants(&builds, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(files(&selection.determinants[0]), ["small"]);
+        assert!(selection.indistinguishable.is_empty());
+    }
+
+    #[test]
+    fn determinants_match_their_build() {
+        let builds = [
+            build(&[("data/a", "a1"), ("b", "b")]),
+            build(&[("data/a", "a2"), ("b", "b")]),
+        ];
+        let selection = select_determinants(&builds, HashAlgorithm::Sha256).unwrap();
+        for (build, determinants) in builds.iter().zip(&selection.determinants) {
+            assert_eq!(files(determinants), ["data/a"]);
+            assert_eq!(determinants[0].size, Some(2));
+            assert!(determinants[0].sha256.is_some());
+            assert!(determinants[0].matches(build.path()).unwrap());
+        }
+        assert!(
+            !selection.determinants[1][0]
+                .matches(builds[0].path())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn missing_files_are_absent_determinants() {
+        let builds = [build(&[("a", "a")]), build(&[("a", "a"), ("new", "n")])];
+        let selection = select_determinants(&builds, HashAlgorithm::Xxh3).unwrap();
+        assert_eq!(files(&selection.determinants[0]), ["new"]);
+        assert!(selection.determinants[0][0].absent);
+        assert!(!selection.determinants[1][0].absent);
+        assert!(selection.determinants[1][0].xxh3.is_some());
+    }
+
+    #[test]
+    fn fewest_files_are_chosen() {
+        let builds = [
+            build(&[("all", "1"), ("last", "1"), ("other", "1")]),
+            build(&[("all", "2"), ("last", "1"), ("other", "2")]),
+            build(&[("all", "3"), ("last", "3"), ("other", "2")]),
+        ];
+        let selection = select_determinants(&builds, HashAlgorithm::Sha256).unwrap();
+        for determinants in &selection.determinants {
+            assert_eq!(files(determinants), ["all"]);
+        }
+    }
+
+    #[test]
+    fn smallest_file_wins_ties() {
+        let builds = [
+            build(&[("big", "big file 1"), ("small", "1")]),
+            build(&[("big", "big file 2"), ("small", "2")]),
+        ];
+        let selection = select_determinants(&builds, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(files(&selection.determinants[0]), ["small"]);
+    }
+
+    #[test]
+    fn identical_builds_are_reported() {
+        let builds = [
+            build(&[("a", "1")]),
+            build(&[("a", "2")]),
+            build(&[("a", "2")]),
+        ];
+        let selection = select_determinants(&builds, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(selection.indistinguishable, [(1, 2)]);
+    }
+
+    #[test]
+    fn patcher_directory_is_ignored() {
+        let builds = [
+            build(&[("a", "1"), (".thl-patcher/state.yaml", "1")]),
+            build(&[("a", "2"), (".thl-patcher/state.yaml", "2")]),
+        ];
+        let selection = select_determinants(&builds, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(files(&selection.determinants[0]), ["a"]);
+    }
+}
@@ -0,0 +1,202 @@
+use std::{io, path::Path};
+
+use crate::{
+    download::PatchOptions, error::DownloadAndPatchError, structures::source::VersionTransitionRef,
+};
+
+#[cfg(unix)]
+fn available_space(path: &Path) -> io::Result<u64> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a valid nul-terminated string, and `stat` is only read if the call succeeded
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(windows)]
+fn available_space(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available = 0;
+    // SAFETY: `path` is a valid nul-terminated wide string, and null pointers are allowed for the
+    // values we don't need
+    let res = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &raw mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if res == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+pub(crate) fn ensure_space(path: &Path, required: u64) -> Result<(), DownloadAndPatchError> {
+    let available = match available_space(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+            log::warn!(
+                "cannot get the available space of `{}`, skipping check",
+                path.display()
+            );
+            return Ok(());
+        }
+        Err(e) => Err(e)?,
+    };
+    log::debug!(
+        "`{}`: {required} bytes required, {available} bytes available",
+        path.display()
+    );
+    if available < required {
+        return Err(DownloadAndPatchError::NotEnoughSpace {
+            path: path.to_path_buf(),
+            required,
+            available,
+        });
+    }
+    Ok(())
+}
+
+///
+/// Estimates the peak disk usage of the whole transition chain, in bytes. Every archive is
+/// downloaded before the first transition is applied, and each transition then needs room for
+/// the patched files staged before being moved in place.
+///
+/// Transitions are applied one by one, and their archive and staging files are removed in
+/// between. When backups are kept, the files replaced by each transition stay on the disk though,
+/// and are assumed to be as big as the files replacing them.
+///
+pub fn estimate_peak_usage(transitions: &[VersionTransitionRef], options: &PatchOptions) -> u64 {
+    let mut archives: u64 = transitions
+        .iter()
+        .filter_map(|x| x.patch.and_then(|x| x.archive_size))
+        .sum();
+    let mut peak = archives;
+    let mut backups = 0;
+    for transition in transitions {
+        let archive = transition.patch.and_then(|x| x.archive_size);
+        let unpacked = transition.patch.and_then(|x| x.unpacked_size);
+        if archive.is_none() || unpacked.is_none() {
+            log::warn!(
+                "missing size metadata from `{}` to `{}`, disk usage will be underestimated",
+                transition.old.name,
+                transition.new.name
+            );
+        }
+        peak = peak.max(backups + archives + unpacked.unwrap_or_default());
+        archives -= archive.unwrap_or_default();
+        if options.keep_backups {
+            backups += unpacked.unwrap_or_default();
+        }
+    }
+    peak
+}
+
+///
+/// Checks that the volume of `original` has enough free space to apply every transition, based
+/// on the sizes declared in the source.
+///
+pub fn check_available_space(
+    original: &Path,
+    transitions: &[VersionTransitionRef],
+    options: &PatchOptions,
+) -> Result<(), DownloadAndPatchError> {
+    ensure_space(original, estimate_peak_usage(transitions, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::source::Source;
+
+    fn source(yaml: &str) -> Source {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    const CHAIN: &str = "
+versions:
+  - name: v0
+    update_link: v0-v1
+    archive_size: 10
+    unpacked_size: 100
+    determinants:
+      - file: a
+  - name: v1
+    update_link: v1-v2
+    archive_size: 20
+    unpacked_size: 50
+    determinants:
+      - file: b
+  - name: v2
+    determinants:
+      - file: c
+";
+
+    #[test]
+    fn every_archive_is_downloaded_first() {
+        let source = source(CHAIN);
+        let transitions = source.plan_to_latest(0);
+        // Both archives, and the files of the first transition
+        assert_eq!(
+            estimate_peak_usage(&transitions, &PatchOptions::default()),
+            10 + 20 + 100
+        );
+        assert_eq!(
+            estimate_peak_usage(&transitions[1..], &PatchOptions::default()),
+            20 + 50
+        );
+    }
+
+    #[test]
+    fn backups_accumulate() {
+        let source = source(CHAIN);
+        let transitions = source.plan_to_latest(0);
+        let options = PatchOptions {
+            keep_backups: true,
+            ..Default::default()
+        };
+        // The backup of the first transition, the second archive and its files
+        assert_eq!(estimate_peak_usage(&transitions, &options), 100 + 20 + 50);
+    }
+
+    #[test]
+    fn missing_sizes_count_as_zero() {
+        let source = source(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    archive_size: 10
+    determinants:
+      - file: a
+  - name: v1
+    determinants:
+      - file: b
+",
+        );
+        let transitions = source.plan_to_latest(0);
+        assert_eq!(
+            estimate_peak_usage(&transitions, &PatchOptions::default()),
+            10
+        );
+        assert_eq!(estimate_peak_usage(&[], &PatchOptions::default()), 0);
+    }
+}
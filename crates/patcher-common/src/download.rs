@@ -1,15 +1,51 @@
-use std::{io::{self, Cursor}, path::Path};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
 
+use sha2::{Digest, Sha256};
 use tar::Archive;
-use tempfile::tempdir;
+use tempfile::{TempDir, tempfile_in};
+use thl_patcher::CancellationToken;
 use walkdir::WalkDir;
 use xz2::read::XzDecoder;
 
-use crate::{error::DownloadAndPatchError, structures::source::VersionTransitionRef};
+use crate::{
+    backup::Backup,
+    disk_space::{check_available_space, ensure_space},
+    error::DownloadAndPatchError,
+    history::{self, HistoryEntry, Outcome},
+    lock::InstallLock,
+    manifest::{ConflictPolicy, Conflicts, conflicts_dir, move_aside},
+    preflight::{archive_files, check_writable},
+    state::InstalledState,
+    structures::source::{Version, VersionTransitionRef},
+};
+
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Name of the directory, inside the install directory, where the patcher keeps its own files
+pub const PATCHER_DIR: &str = ".thl-patcher";
+const STAGING_PREFIX: &str = "staging-";
+
+#[derive(Debug, Default, Clone)]
+pub struct PatchOptions {
+    /// Keeps the files replaced by each transition, so that it can be rolled back
+    pub keep_backups: bool,
+    /// What to do with the files modified by the user, when the installed version has a manifest
+    pub conflict_policy: ConflictPolicy,
+    /// The files modified by the user, found with [`Conflicts::find`] before updating. Only the
+    /// ones replaced by a transition are handled according to `conflict_policy`.
+    pub conflicts: Option<Conflicts>,
+}
 
 pub trait ProgressReporter {
-    /// Ran each time a new version is processed. Typically a good time to print a "Downloading" message
+    /// Ran each time the archive of a new version starts downloading. Every archive is downloaded
+    /// before the first one is applied
     fn on_start_new_version(&mut self, _transition: &VersionTransitionRef) {}
+    /// Ran when a full install starts, instead of [`Self::on_start_new_version`]
+    fn on_start_full_install(&mut self, _version: &Version) {}
     /// Ran each time a new file is being processed. Typically a good time to print a "Patching" message
     fn on_patching_file(&mut self, _path: &Path) {}
     /// Ran each time a version patch ends. Can be useful for cleaning up some of the interface
@@ -18,49 +54,614 @@ pub trait ProgressReporter {
     fn on_finish(&mut self) {}
 }
 
-#[allow(clippy::cast_precision_loss)]
+///
+/// Downloads the whole content behind `link` into a temporary file created in `dir`, checking
+/// `cancel` between each chunk. Returns the file along with its SHA-256
+///
+pub(crate) fn download(
+    link: &str,
+    dir: &Path,
+    cancel: &CancellationToken,
+) -> Result<(File, String), DownloadAndPatchError> {
+    let mut response = minreq::get(link).send_lazy()?;
+    let mut file = tempfile_in(dir)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; DOWNLOAD_CHUNK_SIZE];
+    loop {
+        if cancel.is_cancelled() {
+            return Err(DownloadAndPatchError::Cancelled);
+        }
+        match response.read(&mut buffer)? {
+            0 => break,
+            n => {
+                file.write_all(&buffer[..n])?;
+                hasher.update(&buffer[..n]);
+            }
+        }
+    }
+    file.rewind()?;
+
+    let mut hash = [0; 64];
+    let hash = match base16ct::lower::encode_str(&hasher.finalize(), &mut hash) {
+        Ok(x) => x.to_string(),
+        Err(e) => unreachable!("64-byte should always be enough: {e}"),
+    };
+    Ok((file, hash))
+}
+
+///
+/// Creates a staging directory inside the install directory, so that staged files can be moved
+/// in place with a simple rename
+///
+pub(crate) fn create_staging_dir(original: &Path) -> io::Result<TempDir> {
+    let patcher_dir = original.join(PATCHER_DIR);
+    std::fs::create_dir_all(&patcher_dir)?;
+    tempfile::Builder::new()
+        .prefix(STAGING_PREFIX)
+        .tempdir_in(patcher_dir)
+}
+
+///
+/// Removes staging directories left behind by a patcher that didn't terminate properly
+///
+fn remove_stale_staging_dirs(original: &Path) -> io::Result<()> {
+    let patcher_dir = original.join(PATCHER_DIR);
+    if !patcher_dir.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(patcher_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir()
+            && entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(STAGING_PREFIX)
+        {
+            log::info!(
+                "removing stale staging directory `{}`",
+                entry.path().display()
+            );
+            std::fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+///
+/// A transition whose archive was downloaded, waiting to be applied
+///
+struct PreparedTransition<'a> {
+    transition: VersionTransitionRef<'a>,
+    /// Holds the archive, and the patched files in a subdirectory
+    staging_dir: TempDir,
+    archive_file: File,
+    archive_sha256: String,
+    /// Files of the install directory replaced by the archive
+    files: Vec<PathBuf>,
+}
+
+///
+/// Downloads the archive of `transition` in a new staging directory, and lists its files
+///
+fn prepare_transition<'a>(
+    original: &Path,
+    transition: VersionTransitionRef<'a>,
+    cancel: &CancellationToken,
+) -> Result<PreparedTransition<'a>, DownloadAndPatchError> {
+    let staging_dir = create_staging_dir(original)?;
+    let patch = transition
+        .patch
+        .ok_or(DownloadAndPatchError::NoUpdateLink)?;
+    let (mut archive_file, archive_sha256) = download(patch.link, staging_dir.path(), cancel)?;
+    let files = archive_files(&mut Archive::new(XzDecoder::new(BufReader::new(
+        &archive_file,
+    ))))?;
+    archive_file.rewind()?;
+    Ok(PreparedTransition {
+        transition,
+        staging_dir,
+        archive_file,
+        archive_sha256,
+        files,
+    })
+}
+
+///
+/// Applies a single downloaded transition on `original`, filling `entry` as the information
+/// becomes available. `conflicts` are the files modified by the user that weren't handled yet.
+///
+fn apply_transition<P: ProgressReporter>(
+    original: &Path,
+    prepared: PreparedTransition,
+    options: &PatchOptions,
+    cancel: &CancellationToken,
+    progress: &mut P,
+    conflicts: &mut Vec<PathBuf>,
+    entry: &mut HistoryEntry,
+) -> Result<(), DownloadAndPatchError> {
+    let staged_files = prepared.staging_dir.path().join("files");
+    let vanilla_files = prepared.staging_dir.path().join("vanilla");
+    entry.archive_sha256 = Some(prepared.archive_sha256);
+    let overwritten = resolve_conflicts(
+        prepared.staging_dir.path(),
+        &vanilla_files,
+        &prepared.files,
+        options,
+        cancel,
+        conflicts,
+        entry,
+    )?;
+
+    let mut archive_file = prepared.archive_file;
+    let skipped = &entry.skipped;
+    let is_overwritten = |path: &Path| overwritten.iter().any(|x| x == path);
+    patch(
+        original,
+        &mut archive_file,
+        &staged_files,
+        cancel,
+        |path| !skipped.iter().any(|x| x == path) && !is_overwritten(path),
+        progress,
+    )?;
+    if !overwritten.is_empty() {
+        // The files modified by the user are patched from the clean copy of the installed version
+        patch(
+            &vanilla_files,
+            &mut archive_file,
+            &staged_files,
+            cancel,
+            is_overwritten,
+            progress,
+        )?;
+    }
+
+    let mut backup = if options.keep_backups {
+        Some(Backup::create(original, &prepared.transition)?)
+    } else {
+        None
+    };
+
+    // Past this point, the transition is committed: cancelling would leave a half-updated
+    // install, so the token isn't checked anymore until the next transition
+    let res = overwrite(original, &vanilla_files, &overwritten, options)
+        .and_then(|()| commit(original, &staged_files, backup.as_mut(), entry));
+
+    // The backup is saved even if the commit failed midway, so that it can still be rolled back
+    if let Some(backup) = &backup {
+        backup.save()?;
+    }
+    res
+}
+
+///
+/// Applies the archive `archive_file` on the files of `old` for which `filter` returns `true`,
+/// staging the patched files in `destination`
+///
+fn patch<P: ProgressReporter>(
+    old: &Path,
+    archive_file: &mut File,
+    destination: &Path,
+    cancel: &CancellationToken,
+    filter: impl Fn(&Path) -> bool,
+    progress: &mut P,
+) -> Result<(), DownloadAndPatchError> {
+    archive_file.rewind()?;
+    let mut archive = Archive::new(XzDecoder::new(BufReader::new(&*archive_file)));
+    match thl_patcher::patch_from_tar_filtered(
+        old,
+        &mut archive,
+        destination,
+        cancel,
+        filter,
+        |s| progress.on_patching_file(&s.path),
+    ) {
+        Ok(()) => Ok(()),
+        Err(thl_patcher::PatchError::Cancelled) => Err(DownloadAndPatchError::Cancelled),
+        Err(e) => Err(e)?,
+    }
+}
+
+///
+/// Moves the `overwritten` files of `original` to the patcher directory, and replaces them with
+/// their clean copy from `vanilla_files`
+///
+fn overwrite(
+    original: &Path,
+    vanilla_files: &Path,
+    overwritten: &[PathBuf],
+    options: &PatchOptions,
+) -> Result<(), DownloadAndPatchError> {
+    let Some(found) = &options.conflicts else {
+        return Ok(());
+    };
+    if overwritten.is_empty() {
+        return Ok(());
+    }
+    let conflicts_dir = conflicts_dir(original, &found.version);
+    for file in overwritten {
+        if original.join(file).exists() {
+            move_aside(original, file, &conflicts_dir)?;
+        }
+        std::fs::rename(vanilla_files.join(file), original.join(file))?;
+    }
+    Ok(())
+}
+
+///
+/// Sets the outcome of `entry` according to `res`, and appends it to the history of `original`
+///
+fn record<T>(
+    original: &Path,
+    mut entry: HistoryEntry,
+    res: Result<T, DownloadAndPatchError>,
+) -> Result<T, DownloadAndPatchError> {
+    match &res {
+        Ok(_) => entry.outcome = Outcome::Success,
+        Err(DownloadAndPatchError::Cancelled) => entry.outcome = Outcome::Cancelled,
+        Err(e) => {
+            entry.outcome = Outcome::Failed;
+            entry.error = Some(e.to_string());
+        }
+    }
+    if let Err(e) = history::append(original, &entry) {
+        log::error!("couldn't write the update history: {e}");
+    }
+    res
+}
+
+///
+/// Moves every file of `staged_files` in place inside `original`, after storing the file it
+/// replaces in `backup`
+///
+fn commit(
+    original: &Path,
+    staged_files: &Path,
+    mut backup: Option<&mut Backup>,
+    entry: &mut HistoryEntry,
+) -> Result<(), DownloadAndPatchError> {
+    for file in WalkDir::new(staged_files) {
+        let file = file?;
+        if !file.file_type().is_file() {
+            continue;
+        }
+        let path = file.into_path();
+        let Ok(suffix) = path.strip_prefix(staged_files) else {
+            unreachable!("path is always a child of staged_files");
+        };
+        if let Some(backup) = backup.as_deref_mut()
+            && original.join(suffix).exists()
+        {
+            backup.store(original, suffix)?;
+        }
+        let destination = original.join(suffix);
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&path, destination)?;
+        entry.files.push(suffix.to_path_buf());
+    }
+    Ok(())
+}
+
+///
+/// Applies `options.conflict_policy` to the `conflicts` that are among the `files` replaced by
+/// a transition. Overwritten files are removed from `conflicts`, and skipped ones are recorded in
+/// `entry`.
+///
+/// Returns the files to overwrite, whose clean copy is downloaded into `vanilla_files`. The
+/// downloads are made in `staging_dir`.
+///
+fn resolve_conflicts(
+    staging_dir: &Path,
+    vanilla_files: &Path,
+    files: &[PathBuf],
+    options: &PatchOptions,
+    cancel: &CancellationToken,
+    conflicts: &mut Vec<PathBuf>,
+    entry: &mut HistoryEntry,
+) -> Result<Vec<PathBuf>, DownloadAndPatchError> {
+    let replaced: Vec<PathBuf> = files
+        .iter()
+        .filter(|x| conflicts.contains(x))
+        .cloned()
+        .collect();
+    if replaced.is_empty() {
+        return Ok(Vec::new());
+    }
+    match (options.conflict_policy, &options.conflicts) {
+        (ConflictPolicy::Overwrite, Some(found)) => {
+            found
+                .manifest
+                .download_files(&replaced, staging_dir, vanilla_files, cancel)?;
+            conflicts.retain(|x| !replaced.contains(x));
+            Ok(replaced)
+        }
+        (ConflictPolicy::Skip, _) => {
+            log::info!("{} modified file(s) won't be updated", replaced.len());
+            entry.skipped = replaced;
+            Ok(Vec::new())
+        }
+        _ => Err(DownloadAndPatchError::Conflicts(replaced)),
+    }
+}
+
+///
+/// Applies every transition on `original`, one after the other.
+///
+/// The cancellation token is checked during downloads and between patched files. Files are
+/// patched in a staging directory inside `original` before being moved in place, so a
+/// cancellation leaves the current transition untouched, and previous transitions fully applied.
+///
+/// Before downloading anything, the free space of the install volume is checked against the sizes
+/// declared in the source. Every archive is then downloaded, and every file they replace is
+/// checked to be writable, before the first transition is applied. The install directory stays
+/// locked for the whole update, so that another patcher cannot interleave its writes.
+///
+/// The files modified by the user given in [`PatchOptions::conflicts`] are handled according to
+/// [`PatchOptions::conflict_policy`], before the first transition replacing them is written.
+///
+/// Each attempted transition is recorded in the history of the install directory, and the
+/// installed version is recorded in its state file to speed up the next detection. When asked
+/// to, the replaced files are kept as a backup, allowing [`crate::backup::rollback`]. Only the
+/// [`crate::backup::MAX_BACKUPS`] most recent backups are kept.
+///
 pub fn download_and_patch<'a>(
     original: &Path,
     transitions: impl Iterator<Item = VersionTransitionRef<'a>>,
+    options: &PatchOptions,
+    cancel: &CancellationToken,
     mut progress: impl ProgressReporter,
 ) -> Result<(), DownloadAndPatchError> {
-    for transition @ VersionTransitionRef { old, .. } in transitions {
+    let _lock = InstallLock::acquire(original)?;
+    let transitions: Vec<_> = transitions.collect();
+    check_available_space(original, &transitions, options)?;
+    remove_stale_staging_dirs(original)?;
+
+    // Every archive is downloaded before the first transition is applied, so that a file blocked
+    // late in the chain is reported before changing anything
+    let mut prepared = Vec::with_capacity(transitions.len());
+    for transition in transitions {
         progress.on_start_new_version(&transition);
-        // A temporary directory where patched files go
-        let temp_dir = tempdir()?;
-
-        let update_link = old
-            .update_link
-            .as_ref()
-            .ok_or(DownloadAndPatchError::NoUpdateLink)?;
-        let archive_content = minreq::get(update_link).send()?.into_bytes();
-        let decoder = XzDecoder::new(Cursor::new(archive_content));
-        let mut archive = Archive::new(decoder);
-
-        thl_patcher::patch_from_tar(original, &mut archive, temp_dir.path(), |s| {
-            progress.on_patching_file(&s.path);
-        })?;
-
-        for file in WalkDir::new(temp_dir.path()) {
-            let file = file?;
-            if !file.file_type().is_file() {
-                continue;
-            }
-            let path = file.into_path();
-            let Ok(suffix) = path.strip_prefix(temp_dir.path()) else {
-                unreachable!("path is always a child of temp_dir");
-            };
-            let destination = original.join(suffix);
-            match std::fs::rename(&path, &destination) {
-                Ok(()) => (),
-                Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
-                    std::fs::copy(&path, original.join(suffix))?;
-                }
-                Err(e) => Err(e)?,
-            }
+        let entry = HistoryEntry::new(&transition);
+        match prepare_transition(original, transition, cancel) {
+            Ok(x) => prepared.push(x),
+            Err(e) => return record(original, entry, Err(e)),
+        }
+    }
+    if let Some(first) = prepared.first() {
+        let mut files: Vec<_> = prepared.iter().flat_map(|x| x.files.clone()).collect();
+        files.sort_unstable();
+        files.dedup();
+        if let Err(e) = check_writable(original, &files) {
+            return record(original, HistoryEntry::new(&first.transition), Err(e));
+        }
+    }
+
+    let mut conflicts = options
+        .conflicts
+        .as_ref()
+        .map(|x| x.files.clone())
+        .unwrap_or_default();
+    let mut installed = None;
+    for prepared in prepared {
+        if cancel.is_cancelled() {
+            return Err(DownloadAndPatchError::Cancelled);
         }
+        let version = prepared.transition.new;
+        let mut entry = HistoryEntry::new(&prepared.transition);
+        let res = apply_transition(
+            original,
+            prepared,
+            options,
+            cancel,
+            &mut progress,
+            &mut conflicts,
+            &mut entry,
+        );
+        record(original, entry, res)?;
+
+        installed = Some(version);
         progress.on_version_patch_end();
     }
+    if let Some(version) = installed {
+        if let Err(e) = InstalledState::write(original, version) {
+            log::error!("couldn't write the state file: {e}");
+        }
+        if options.keep_backups
+            && let Err(e) = Backup::prune(original, version.component.as_deref())
+        {
+            log::error!("couldn't remove old backups: {e}");
+        }
+    }
+    progress.on_finish();
+    Ok(())
+}
+
+///
+/// Installs a clean copy of `version` in `original` from its full archive, for when no version
+/// could be detected there. Files of `original` that the archive doesn't contain are left as is.
+///
+/// The archive is unpacked in a staging directory before being moved in place, like the files
+/// of [`download_and_patch`], and the install is recorded in the history and the state file.
+///
+pub fn download_full_install(
+    original: &Path,
+    version: &Version,
+    cancel: &CancellationToken,
+    mut progress: impl ProgressReporter,
+) -> Result<(), DownloadAndPatchError> {
+    let full_install = version
+        .full_install
+        .as_ref()
+        .ok_or(DownloadAndPatchError::NoUpdateLink)?;
+    std::fs::create_dir_all(original)?;
+    let _lock = InstallLock::acquire(original)?;
+    let required = full_install.archive_size.unwrap_or_default()
+        + full_install.unpacked_size.unwrap_or_default();
+    ensure_space(original, required)?;
+    remove_stale_staging_dirs(original)?;
+    progress.on_start_full_install(version);
+
+    let mut entry = HistoryEntry::full_install(version);
+    let res = (|| -> Result<(), DownloadAndPatchError> {
+        let staging_dir = create_staging_dir(original)?;
+        let staged_files = staging_dir.path().join("files");
+        let (mut archive_file, archive_sha256) =
+            download(&full_install.link, staging_dir.path(), cancel)?;
+        entry.archive_sha256 = Some(archive_sha256);
+
+        let files = archive_files(&mut Archive::new(XzDecoder::new(BufReader::new(
+            &archive_file,
+        ))))?;
+        archive_file.rewind()?;
+        check_writable(original, &files)?;
+
+        std::fs::create_dir_all(&staged_files)?;
+        let mut archive = Archive::new(XzDecoder::new(BufReader::new(archive_file)));
+        for file in archive.entries()? {
+            if cancel.is_cancelled() {
+                return Err(DownloadAndPatchError::Cancelled);
+            }
+            let mut file = file?;
+            progress.on_patching_file(&file.path()?);
+            file.unpack_in(&staged_files)?;
+        }
+
+        // Like for transitions, cancelling isn't possible anymore past this point
+        commit(original, &staged_files, None, &mut entry)
+    })();
+    record(original, entry, res)?;
+
+    if let Err(e) = InstalledState::write(original, version) {
+        log::error!("couldn't write the state file: {e}");
+    }
+    progress.on_version_patch_end();
     progress.on_finish();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{io::BufRead, net::TcpListener};
+
+    use xz2::write::XzEncoder;
+
+    use super::*;
+
+    struct NoProgress;
+
+    impl ProgressReporter for NoProgress {}
+
+    ///
+    /// Serves `body` over HTTP on the loopback interface, returning its URL
+    ///
+    fn serve(body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                // The request is read up to the blank line ending its headers
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                    line.clear();
+                }
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(&body);
+            }
+        });
+        format!("http://{address}/full.tar.xz")
+    }
+
+    fn full_archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(XzEncoder::new(Vec::new(), 6));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn version(link: &str) -> Version {
+        serde_yaml::from_str(&format!(
+            "
+name: v1
+full_install:
+  link: {link}
+determinants:
+  - file: data/a
+    size: 1
+"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn full_install_unpacks_the_archive() {
+        let original = tempfile::tempdir().unwrap();
+        std::fs::write(original.path().join("kept"), "kept").unwrap();
+        let version = version(&serve(full_archive(&[("data/a", "a"), ("b", "bb")])));
+
+        download_full_install(
+            original.path(),
+            &version,
+            &CancellationToken::new(),
+            NoProgress,
+        )
+        .unwrap();
+        let read = |file| std::fs::read_to_string(original.path().join(file)).unwrap();
+        assert_eq!(read("data/a"), "a");
+        assert_eq!(read("b"), "bb");
+        assert_eq!(read("kept"), "kept");
+
+        let state = InstalledState::read(original.path(), None).unwrap();
+        assert_eq!(state.version, "v1");
+        let history = history::read(original.path()).unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(history[0].is_full_install());
+        assert_eq!(history[0].outcome, Outcome::Success);
+        assert_eq!(history[0].files.len(), 2);
+    }
+
+    #[test]
+    fn cancelled_full_install_changes_nothing() {
+        let original = tempfile::tempdir().unwrap();
+        let version = version(&serve(full_archive(&[("data/a", "a")])));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let res = download_full_install(original.path(), &version, &cancel, NoProgress);
+        assert!(matches!(res, Err(DownloadAndPatchError::Cancelled)));
+        assert!(!original.path().join("data/a").exists());
+        assert!(InstalledState::read(original.path(), None).is_none());
+        let history = history::read(original.path()).unwrap();
+        assert_eq!(history[0].outcome, Outcome::Cancelled);
+    }
+
+    #[test]
+    fn full_install_requires_an_archive() {
+        let original = tempfile::tempdir().unwrap();
+        let mut version = version("unused");
+        version.full_install = None;
+
+        let res = download_full_install(
+            original.path(),
+            &version,
+            &CancellationToken::new(),
+            NoProgress,
+        );
+        assert!(matches!(res, Err(DownloadAndPatchError::NoUpdateLink)));
+        assert!(history::read(original.path()).unwrap().is_empty());
+    }
+}
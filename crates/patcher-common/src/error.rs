@@ -1,7 +1,9 @@
-use std::io;
+use std::{io, path::PathBuf};
 
 use thiserror::Error;
 
+use crate::{hash::HashAlgorithm, preflight::BlockedFile};
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum Error {
@@ -27,6 +29,52 @@ pub enum GlobalErrorType {
     SourceNotFound(#[from] minreq::Error),
     #[error("source format error: {0}")]
     SourceFormatError(#[from] serde_yaml::Error),
+    #[error("invalid source: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    InvalidSource(Vec<ValidationError>),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    #[error("version name `{0}` is used several times")]
+    DuplicateName(String),
+    #[error("channel name `{0}` is used several times")]
+    DuplicateChannel(String),
+    #[error("invalid sha256 for the patcher download `{0}`")]
+    InvalidPatcherChecksum(String),
+    #[error("component name `{0}` is used several times")]
+    DuplicateComponent(String),
+    #[error("version `{0}` cannot be reached from the first version")]
+    Unreachable(String),
+    #[error("version `{version}` jumps to unknown version `{to}`")]
+    UnknownJumpTarget { version: String, to: String },
+    #[error("invalid {algorithm} hash for file `{file}` of version `{version}`")]
+    InvalidHash {
+        version: String,
+        file: String,
+        algorithm: HashAlgorithm,
+    },
+    #[error("file `{file}` of version `{version}` has several hashes")]
+    SeveralHashes { version: String, file: String },
+    #[error("file `{file}` of version `{version}` must be absent but has a size or a hash")]
+    ContradictoryDeterminant { version: String, file: String },
+    #[error("version `{0}` has no determinants")]
+    NoDeterminants(String),
+    #[error("versions `{first}` and `{second}` have the same determinants")]
+    Ambiguous { first: String, second: String },
+    #[error(
+        "version `{0}` is shared by several platforms, but isn't followed by the same version on each of them"
+    )]
+    SharedUpdateLink(String),
+    #[error("{0}")]
+    Channel(#[from] ChannelError),
+}
+
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("another patcher is already updating this directory (pid: {pid:?})")]
+    Locked { pid: Option<u32> },
 }
 
 #[derive(Error, Debug)]
@@ -41,8 +89,116 @@ pub enum DownloadAndPatchError {
     PatchError(#[from] thl_patcher::PatchError),
     #[error("no update link indicated")]
     NoUpdateLink,
+    #[error("update cancelled")]
+    Cancelled,
+    #[error(
+        "not enough space on the volume of `{}`: {required} bytes required, {available} bytes available",
+        path.display()
+    )]
+    NotEnoughSpace {
+        path: PathBuf,
+        required: u64,
+        available: u64,
+    },
+    #[error("another patcher is already updating this directory (pid: {pid:?})")]
+    Locked { pid: Option<u32> },
+    #[error("{} file(s) cannot be written", .0.len())]
+    BlockedFiles(Vec<BlockedFile>),
+    #[error("{} file(s) were modified", .0.len())]
+    Conflicts(Vec<PathBuf>),
+    #[error("manifest error: {0}")]
+    Manifest(#[from] ManifestError),
+    #[error("the installed version has no manifest")]
+    NoManifest,
+    #[error("the component {0} has no manifest")]
+    NoComponentManifest(String),
+    #[error("the manifest doesn't allow downloading files one by one")]
+    NoFilesLink,
+    #[error("downloaded file `{}` doesn't match the manifest", .0.display())]
+    CorruptedDownload(PathBuf),
+}
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("manifest not found: {0}")]
+    NotFound(#[from] minreq::Error),
+    #[error("manifest format error: {0}")]
+    Format(#[from] serde_yaml::Error),
+}
+
+impl From<LockError> for DownloadAndPatchError {
+    fn from(value: LockError) -> Self {
+        match value {
+            LockError::Io(e) => Self::Io(e),
+            LockError::Locked { pid } => Self::Locked { pid },
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RollbackError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("backup format error: {0}")]
+    Format(#[from] serde_yaml::Error),
+    #[error("another patcher is already updating this directory (pid: {pid:?})")]
+    Locked { pid: Option<u32> },
+    #[error("no backup to restore")]
+    NoBackup,
+    #[error("the backup was made from version `{expected}`, but version `{found}` is installed")]
+    VersionMismatch { expected: String, found: String },
+    #[error("the restored files don't match version `{0}`")]
+    VerificationFailed(String),
+}
+
+impl From<LockError> for RollbackError {
+    fn from(value: LockError) -> Self {
+        match value {
+            LockError::Io(e) => Self::Io(e),
+            LockError::Locked { pid } => Self::Locked { pid },
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("history format error: {0}")]
+    Format(#[from] serde_yaml::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum PlanError {
+    #[error("unknown version `{0}`")]
+    UnknownVersion(String),
+    #[error("version `{to}` cannot be reached from version `{from}`")]
+    Unreachable { from: String, to: String },
 }
 
+#[derive(Error, Debug)]
+pub enum SelfUpdateError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("download error: {0}")]
+    Download(#[from] DownloadAndPatchError),
+    #[error("checksum mismatch: expected {expected}, found {found}")]
+    ChecksumMismatch { expected: String, found: String },
+}
+
+#[derive(Error, Debug)]
+pub enum ComponentError {
+    #[error("unknown component `{0}`")]
+    Unknown(String),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ChannelError {
+    #[error("unknown channel `{0}`")]
+    Unknown(String),
+    #[error("channel `{channel}` branches off unknown version `{branch}`")]
+    BranchNotFound { channel: String, branch: String },
+}
 
 #[derive(Error, Debug)]
 pub enum GetVersionError {
@@ -52,4 +208,4 @@ pub enum GetVersionError {
     VersionNotFound,
     #[error("missing path")]
     MissingPath,
-}
\ No newline at end of file
+}
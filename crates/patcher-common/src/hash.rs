@@ -0,0 +1,176 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
+use xxhash_rust::xxh3::Xxh3;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+///
+/// The algorithms determinants can be hashed with. `xxh3` is much faster, but is only meant to
+/// tell versions apart, not to protect against tampering.
+///
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+    Blake3,
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [Self; 4] = [Self::Sha256, Self::Sha512, Self::Blake3, Self::Xxh3];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Blake3 => "blake3",
+            Self::Xxh3 => "xxh3",
+        }
+    }
+
+    ///
+    /// The length of the hashes produced, in hex characters
+    ///
+    pub const fn hex_len(self) -> usize {
+        match self {
+            Self::Sha256 | Self::Blake3 => 64,
+            Self::Sha512 => 128,
+            Self::Xxh3 => 16,
+        }
+    }
+
+    ///
+    /// Whether `hash` looks like a hash of this algorithm, in lowercase hex
+    ///
+    pub fn is_valid(self, hash: &str) -> bool {
+        hash.len() == self.hex_len() && hash.bytes().all(|x| matches!(x, b'0'..=b'9' | b'a'..=b'f'))
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.name() == s)
+            .ok_or_else(|| format!("unknown hash algorithm `{s}`"))
+    }
+}
+
+fn read_chunks(file: File, mut update: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let len = buffer.len();
+        update(buffer);
+        reader.consume(len);
+    }
+}
+
+///
+/// Computes the lowercase hex hash of the content of `file`
+///
+pub fn hash_file(file: File, algorithm: HashAlgorithm) -> io::Result<String> {
+    let digest = match algorithm {
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            read_chunks(file, |x| hasher.update(x))?;
+            hasher.finalize().to_vec()
+        }
+        HashAlgorithm::Sha512 => {
+            let mut hasher = Sha512::new();
+            read_chunks(file, |x| hasher.update(x))?;
+            hasher.finalize().to_vec()
+        }
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            read_chunks(file, |x| {
+                hasher.update(x);
+            })?;
+            hasher.finalize().as_bytes().to_vec()
+        }
+        HashAlgorithm::Xxh3 => {
+            let mut hasher = Xxh3::new();
+            read_chunks(file, |x| hasher.update(x))?;
+            hasher.digest().to_be_bytes().to_vec()
+        }
+    };
+    Ok(base16ct::lower::encode_string(&digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(content: &str, algorithm: HashAlgorithm) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, content).unwrap();
+        hash_file(File::open(path).unwrap(), algorithm).unwrap()
+    }
+
+    #[test]
+    fn known_hashes() {
+        assert_eq!(
+            hash("abc", HashAlgorithm::Sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash("abc", HashAlgorithm::Sha512),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        assert_eq!(
+            hash("", HashAlgorithm::Blake3),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!(hash("", HashAlgorithm::Xxh3), "2d06800538d394c2");
+    }
+
+    #[test]
+    fn files_larger_than_the_buffer() {
+        let content = "a".repeat(BUFFER_SIZE * 2 + 1);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, &content).unwrap();
+        for algorithm in HashAlgorithm::ALL {
+            let hash = hash_file(File::open(&path).unwrap(), algorithm).unwrap();
+            assert!(algorithm.is_valid(&hash), "{algorithm}: {hash}");
+        }
+        assert_eq!(
+            hash(&content, HashAlgorithm::Xxh3),
+            format!("{:016x}", xxhash_rust::xxh3::xxh3_64(content.as_bytes()))
+        );
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for algorithm in HashAlgorithm::ALL {
+            assert_eq!(algorithm.name().parse::<HashAlgorithm>(), Ok(algorithm));
+        }
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn hash_validity() {
+        assert!(HashAlgorithm::Xxh3.is_valid("2d06800538d394c2"));
+        assert!(!HashAlgorithm::Xxh3.is_valid("2D06800538D394C2"));
+        assert!(!HashAlgorithm::Sha256.is_valid("2d06800538d394c2"));
+    }
+}
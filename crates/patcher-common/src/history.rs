@@ -0,0 +1,200 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    download::PATCHER_DIR,
+    error::HistoryError,
+    structures::source::{Version, VersionTransitionRef},
+};
+
+const HISTORY_FILE: &str = "history.yaml";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Cancelled,
+    Failed,
+}
+
+///
+/// A single transition attempted by the patcher on an install directory
+///
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    /// Empty for a full install, as no version was detected
+    pub from: String,
+    pub to: String,
+    /// SHA-256 of the downloaded archive, if the download went through
+    pub archive_sha256: Option<String>,
+    /// Files of the install directory replaced by this transition
+    pub files: Vec<PathBuf>,
+    /// Files modified by the user, left untouched by this transition
+    #[serde(default)]
+    pub skipped: Vec<PathBuf>,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+    /// The component updated by this transition, `None` for the main versions
+    #[serde(default)]
+    pub component: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(transition: &VersionTransitionRef) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            from: transition.old.name.clone(),
+            to: transition.new.name.clone(),
+            archive_sha256: None,
+            files: Vec::new(),
+            skipped: Vec::new(),
+            outcome: Outcome::Success,
+            error: None,
+            component: transition.new.component.clone(),
+        }
+    }
+
+    pub fn full_install(version: &Version) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            from: String::new(),
+            to: version.name.clone(),
+            archive_sha256: None,
+            files: Vec::new(),
+            skipped: Vec::new(),
+            outcome: Outcome::Success,
+            error: None,
+            component: version.component.clone(),
+        }
+    }
+
+    pub fn restoration(version: &Version) -> Self {
+        Self {
+            from: version.name.clone(),
+            ..Self::full_install(version)
+        }
+    }
+
+    pub fn is_full_install(&self) -> bool {
+        self.from.is_empty()
+    }
+
+    ///
+    /// Whether the entry is a restoration of a clean copy of the installed version, from its
+    /// manifest
+    ///
+    pub fn is_restoration(&self) -> bool {
+        self.from == self.to
+    }
+
+    pub fn formatted_timestamp(&self) -> String {
+        self.timestamp
+            .with_timezone(&Local)
+            .format("%d/%m/%Y %H:%M:%S")
+            .to_string()
+    }
+}
+
+fn history_path(original: &Path) -> PathBuf {
+    original.join(PATCHER_DIR).join(HISTORY_FILE)
+}
+
+///
+/// Appends `entry` to the history of the install directory `original`.
+///
+/// The history is a YAML sequence, so entries are simply appended at the end of the file instead
+/// of rewriting it.
+///
+pub fn append(original: &Path, entry: &HistoryEntry) -> Result<(), HistoryError> {
+    let content = serde_yaml::to_string(std::slice::from_ref(entry))?;
+    let path = history_path(original);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+///
+/// Reads the history of the install directory `original`, from the oldest entry to the newest.
+/// An install that was never updated has an empty history.
+///
+pub fn read(original: &Path) -> Result<Vec<HistoryEntry>, HistoryError> {
+    match std::fs::read_to_string(history_path(original)) {
+        Ok(content) if content.trim().is_empty() => Ok(Vec::new()),
+        Ok(content) => Ok(serde_yaml::from_str(&content)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e)?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(from: &str, to: &str, outcome: Outcome) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: Utc::now(),
+            from: from.to_string(),
+            to: to.to_string(),
+            archive_sha256: Some("00".repeat(32)),
+            files: vec![PathBuf::from("data/file.bin")],
+            skipped: Vec::new(),
+            outcome,
+            error: None,
+            component: None,
+        }
+    }
+
+    #[test]
+    fn empty_history() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn append_then_read() {
+        let dir = tempfile::tempdir().unwrap();
+        append(dir.path(), &entry("v1", "v2", Outcome::Success)).unwrap();
+        let mut failed = entry("v2", "v3", Outcome::Failed);
+        failed.error = Some("network error".to_string());
+        failed.component = Some("voices".to_string());
+        append(dir.path(), &failed).unwrap();
+
+        let history = read(dir.path()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            (history[0].from.as_str(), history[0].to.as_str()),
+            ("v1", "v2")
+        );
+        assert_eq!(history[0].outcome, Outcome::Success);
+        assert_eq!(history[0].files, [PathBuf::from("data/file.bin")]);
+        assert_eq!(history[1].outcome, Outcome::Failed);
+        assert_eq!(history[1].error.as_deref(), Some("network error"));
+        assert_eq!(history[1].component.as_deref(), Some("voices"));
+    }
+
+    #[test]
+    fn full_install_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        append(dir.path(), &entry("", "v1", Outcome::Success)).unwrap();
+        assert!(read(dir.path()).unwrap()[0].is_full_install());
+    }
+
+    #[test]
+    fn restoration_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        append(dir.path(), &entry("v1", "v1", Outcome::Success)).unwrap();
+        let history = read(dir.path()).unwrap();
+        assert!(history[0].is_restoration());
+        assert!(!history[0].is_full_install());
+    }
+}
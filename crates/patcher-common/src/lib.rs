@@ -1,3 +1,14 @@
+pub mod backup;
+pub mod determinants;
+pub mod disk_space;
+pub mod download;
 pub mod error;
+pub mod hash;
+pub mod history;
+pub mod lock;
+pub mod manifest;
+pub mod preflight;
+pub mod self_update;
+pub mod settings;
+pub mod state;
 pub mod structures;
-pub mod download;
\ No newline at end of file
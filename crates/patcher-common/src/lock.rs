@@ -0,0 +1,102 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::Path,
+};
+
+use crate::{download::PATCHER_DIR, error::LockError};
+
+const LOCK_FILE: &str = "lock";
+
+///
+/// An exclusive lock on an install directory, preventing several patchers from updating it at
+/// the same time. The lock is released when this value is dropped.
+///
+/// The lock is held by the OS rather than by the mere presence of the file, so a lock file left
+/// behind by a patcher that died is considered stale and is taken over.
+///
+pub struct InstallLock {
+    _file: File,
+}
+
+impl InstallLock {
+    pub fn acquire(original: &Path) -> Result<Self, LockError> {
+        let patcher_dir = original.join(PATCHER_DIR);
+        std::fs::create_dir_all(&patcher_dir)?;
+        // The file must not be truncated before being locked, as it contains the PID of the
+        // current holder
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(patcher_dir.join(LOCK_FILE))?;
+
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                let mut content = String::new();
+                // Some platforms don't allow reading a locked file, the PID is only informative
+                let pid = file
+                    .read_to_string(&mut content)
+                    .ok()
+                    .and_then(|_| content.trim().parse().ok());
+                return Err(LockError::Locked { pid });
+            }
+            Err(TryLockError::Error(e)) => Err(e)?,
+        }
+
+        let mut previous = String::new();
+        if file.read_to_string(&mut previous).is_ok() && !previous.trim().is_empty() {
+            log::info!("taking over stale lock left by process {}", previous.trim());
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(std::process::id().to_string().as_bytes())?;
+        file.flush()?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_acquire_is_refused() {
+        let original = tempfile::tempdir().unwrap();
+        let _lock = InstallLock::acquire(original.path()).unwrap();
+
+        match InstallLock::acquire(original.path()) {
+            Err(LockError::Locked { pid }) => {
+                // Some platforms don't allow reading the PID of the holder
+                if let Some(pid) = pid {
+                    assert_eq!(pid, std::process::id());
+                }
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("the lock was acquired twice"),
+        }
+    }
+
+    #[test]
+    fn lock_is_released_on_drop() {
+        let original = tempfile::tempdir().unwrap();
+        drop(InstallLock::acquire(original.path()).unwrap());
+        assert!(InstallLock::acquire(original.path()).is_ok());
+    }
+
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let original = tempfile::tempdir().unwrap();
+        let patcher_dir = original.path().join(PATCHER_DIR);
+        std::fs::create_dir_all(&patcher_dir).unwrap();
+        std::fs::write(patcher_dir.join(LOCK_FILE), "4294967295").unwrap();
+
+        let _lock = InstallLock::acquire(original.path()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(patcher_dir.join(LOCK_FILE)).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+}
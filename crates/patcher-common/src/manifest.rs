@@ -0,0 +1,401 @@
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde::Deserialize;
+use thl_patcher::CancellationToken;
+use walkdir::WalkDir;
+
+use crate::{
+    download::{PATCHER_DIR, create_staging_dir, download},
+    error::{DownloadAndPatchError, ManifestError},
+    history::{self, HistoryEntry, Outcome},
+    lock::InstallLock,
+    self_update::patcher_files,
+    state::InstalledState,
+    structures::source::{Determinants, Version},
+};
+
+const CONFLICTS_DIR: &str = "conflicts";
+
+///
+/// What to do with files modified by the user that an update would replace
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// The update stops before the first transition replacing a modified file, with the list
+    /// of those files
+    #[default]
+    Abort,
+    /// Modified files are replaced with the files of the installed version, downloaded and
+    /// patched along with the transition replacing them. They're moved aside when it's applied.
+    Overwrite,
+    /// Modified files are left untouched, and not updated
+    Skip,
+}
+
+///
+/// Every file of a version, as published next to the source
+///
+#[derive(Debug, Deserialize, Clone)]
+pub struct Manifest {
+    pub files: Vec<Determinants>,
+    /// Base URL each file of the version can be downloaded from, by appending its path
+    pub files_link: Option<String>,
+}
+
+impl Manifest {
+    pub fn from_url(url: &str) -> Result<Self, ManifestError> {
+        let response = minreq::get(url).send()?;
+        Ok(serde_yaml::from_slice(response.as_bytes())?)
+    }
+
+    ///
+    /// Fetches the manifest of `version`, if it has one
+    ///
+    pub fn of(version: &Version) -> Result<Option<Self>, ManifestError> {
+        version.manifest.as_deref().map(Self::from_url).transpose()
+    }
+
+    ///
+    /// Gets the files of `original` that don't match this manifest. Missing files aren't
+    /// considered modified, as there's nothing to preserve.
+    ///
+    pub fn find_conflicts(&self, original: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut conflicts = Vec::new();
+        for file in &self.files {
+            if file.absent || !original.join(&file.file).exists() {
+                continue;
+            }
+            if !file.matches(original)? {
+                conflicts.push(PathBuf::from(&file.file));
+            }
+        }
+        Ok(conflicts)
+    }
+
+    ///
+    /// Downloads `files` one by one into `destination`, keeping their relative path, and checks
+    /// each of them. The downloads are made in `staging_dir`.
+    ///
+    pub(crate) fn download_files(
+        &self,
+        files: &[PathBuf],
+        staging_dir: &Path,
+        destination: &Path,
+        cancel: &CancellationToken,
+    ) -> Result<(), DownloadAndPatchError> {
+        let files_link = self
+            .files_link
+            .as_deref()
+            .ok_or(DownloadAndPatchError::NoFilesLink)?;
+        for file in files {
+            let Some(determinant) = self.files.iter().find(|x| Path::new(&x.file) == file) else {
+                continue;
+            };
+            let link = format!("{}/{}", files_link.trim_end_matches('/'), determinant.file);
+            let (mut downloaded, _) = download(&link, staging_dir, cancel)?;
+            let staged = destination.join(file);
+            if let Some(parent) = staged.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut downloaded, &mut File::create(&staged)?)?;
+            if !determinant.matches(destination)? {
+                return Err(DownloadAndPatchError::CorruptedDownload(file.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Replaces `files` of `original` with the ones of `version`. They are all downloaded and
+    /// checked before the files they replace are moved to the patcher directory.
+    ///
+    fn restore(
+        &self,
+        original: &Path,
+        version: &Version,
+        files: &[PathBuf],
+        cancel: &CancellationToken,
+    ) -> Result<(), DownloadAndPatchError> {
+        let staging_dir = create_staging_dir(original)?;
+        let staged_files = staging_dir.path().join("files");
+        self.download_files(files, staging_dir.path(), &staged_files, cancel)?;
+
+        let conflicts_dir = conflicts_dir(original, version);
+        for file in files {
+            let staged = staged_files.join(file);
+            if !staged.exists() {
+                continue;
+            }
+            let destination = original.join(file);
+            if destination.exists() {
+                move_aside(original, file, &conflicts_dir)?;
+            } else if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(&staged, &destination)?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// The files of an install directory modified by the user, according to the manifest of the
+/// version installed there
+///
+#[derive(Debug, Clone)]
+pub struct Conflicts {
+    /// The version installed, whose files are downloaded again by [`ConflictPolicy::Overwrite`]
+    pub version: Version,
+    pub manifest: Manifest,
+    pub files: Vec<PathBuf>,
+}
+
+impl Conflicts {
+    ///
+    /// Fetches the manifest of `version`, and looks for the files of `original` that don't match
+    /// it. Returns `None` if `version` has no manifest.
+    ///
+    pub fn find(original: &Path, version: &Version) -> Result<Option<Self>, DownloadAndPatchError> {
+        let Some(manifest) = Manifest::of(version)? else {
+            return Ok(None);
+        };
+        let files = manifest.find_conflicts(original)?;
+        Ok(Some(Self {
+            version: version.clone(),
+            manifest,
+            files,
+        }))
+    }
+}
+
+///
+/// What [`restore_vanilla`] changed in the install directory
+///
+#[derive(Debug, Clone, Default)]
+pub struct Restoration {
+    /// Files not belonging to the version, moved out of the install directory
+    pub removed: Vec<PathBuf>,
+    /// Files modified or missing, downloaded again
+    pub restored: Vec<PathBuf>,
+}
+
+///
+/// Brings `original` back to a clean copy of `version`, using its manifest. Files that don't
+/// belong to the version are removed, and modified or missing ones are downloaded again. Removed
+/// and replaced files are moved to the patcher directory rather than deleted. The files of the
+/// patcher itself are left in place.
+///
+/// The `components` are the versions installed of the optional components. Their files are part
+/// of the clean install, and restored from their own manifests. A file listed by a component
+/// belongs to it rather than to `version`.
+///
+/// The restoration is recorded in the history of the install directory, and the versions in its
+/// state files.
+///
+pub fn restore_vanilla(
+    original: &Path,
+    version: &Version,
+    components: &[Version],
+    cancel: &CancellationToken,
+) -> Result<Restoration, DownloadAndPatchError> {
+    let _lock = InstallLock::acquire(original)?;
+    let mut entry = HistoryEntry::restoration(version);
+    let res = restore_files(original, version, components, cancel);
+    match &res {
+        Ok(restoration) => {
+            entry.files = restoration
+                .removed
+                .iter()
+                .chain(&restoration.restored)
+                .cloned()
+                .collect();
+        }
+        Err(DownloadAndPatchError::Cancelled) => entry.outcome = Outcome::Cancelled,
+        Err(e) => {
+            entry.outcome = Outcome::Failed;
+            entry.error = Some(e.to_string());
+        }
+    }
+    if let Err(e) = history::append(original, &entry) {
+        log::error!("couldn't write the update history: {e}");
+    }
+    if res.is_ok() {
+        for version in std::iter::once(version).chain(components) {
+            if let Err(e) = InstalledState::write(original, version) {
+                log::error!("couldn't write the state file: {e}");
+            }
+        }
+    }
+    res
+}
+
+fn restore_files(
+    original: &Path,
+    version: &Version,
+    components: &[Version],
+    cancel: &CancellationToken,
+) -> Result<Restoration, DownloadAndPatchError> {
+    let mut manifests = vec![(
+        version,
+        Manifest::of(version)?.ok_or(DownloadAndPatchError::NoManifest)?,
+    )];
+    for component in components {
+        let manifest = Manifest::of(component)?.ok_or_else(|| {
+            DownloadAndPatchError::NoComponentManifest(
+                component.component.clone().unwrap_or_default(),
+            )
+        })?;
+        manifests.push((component, manifest));
+    }
+    // The last manifest listing a file owns it, so that components override the main version
+    let owner = |file: &Path| {
+        manifests.iter().rposition(|(_, manifest)| {
+            manifest
+                .files
+                .iter()
+                .any(|x| !x.absent && Path::new(&x.file) == file)
+        })
+    };
+    let conflicts_dir = conflicts_dir(original, version);
+    let kept = patcher_files(original);
+
+    let mut restoration = Restoration::default();
+    for entry in WalkDir::new(original)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != PATCHER_DIR)
+    {
+        let entry = entry.map_err(io::Error::from)?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(original)
+            .map_err(io::Error::other)?;
+        if !kept.iter().any(|x| x == relative) && owner(relative).is_none() {
+            restoration.removed.push(relative.to_path_buf());
+        }
+    }
+    for file in &restoration.removed {
+        if cancel.is_cancelled() {
+            return Err(DownloadAndPatchError::Cancelled);
+        }
+        move_aside(original, file, &conflicts_dir)?;
+    }
+
+    for (i, (version, manifest)) in manifests.iter().enumerate() {
+        let owned = Manifest {
+            files: manifest
+                .files
+                .iter()
+                .filter(|x| !x.absent && owner(Path::new(&x.file)) == Some(i))
+                .cloned()
+                .collect(),
+            files_link: manifest.files_link.clone(),
+        };
+        let mut restored = owned.find_conflicts(original)?;
+        restored.extend(
+            owned
+                .files
+                .iter()
+                .filter(|x| !original.join(&x.file).exists())
+                .map(|x| PathBuf::from(&x.file)),
+        );
+        if !restored.is_empty() {
+            owned.restore(original, version, &restored, cancel)?;
+        }
+        restoration.restored.extend(restored);
+    }
+    Ok(restoration)
+}
+
+///
+/// Gets the directory where the files of `original` moved aside while handling `version` are
+/// kept. Each run gets its own directory, so that files preserved earlier are never replaced.
+///
+pub(crate) fn conflicts_dir(original: &Path, version: &Version) -> PathBuf {
+    original
+        .join(PATCHER_DIR)
+        .join(CONFLICTS_DIR)
+        .join(&version.name)
+        .join(Utc::now().format("%Y%m%dT%H%M%S%.6fZ").to_string())
+}
+
+///
+/// Moves `file` of `original` into `directory`, keeping its relative path. Fails rather than
+/// replacing a file already preserved there.
+///
+pub(crate) fn move_aside(original: &Path, file: &Path, directory: &Path) -> io::Result<()> {
+    let preserved = directory.join(file);
+    if preserved.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("`{}` already exists", preserved.display()),
+        ));
+    }
+    if let Some(parent) = preserved.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(original.join(file), &preserved)?;
+    log::info!(
+        "moved file `{}` to `{}`",
+        file.display(),
+        preserved.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_aside_keeps_the_relative_path() {
+        let original = tempfile::tempdir().unwrap();
+        let directory = original.path().join("preserved");
+        std::fs::create_dir(original.path().join("data")).unwrap();
+        std::fs::write(original.path().join("data/a"), "modified").unwrap();
+
+        move_aside(original.path(), Path::new("data/a"), &directory).unwrap();
+        assert!(!original.path().join("data/a").exists());
+        assert_eq!(
+            std::fs::read_to_string(directory.join("data/a")).unwrap(),
+            "modified"
+        );
+    }
+
+    #[test]
+    fn moving_aside_never_replaces_a_preserved_file() {
+        let original = tempfile::tempdir().unwrap();
+        let directory = original.path().join("preserved");
+        std::fs::write(original.path().join("a"), "first").unwrap();
+        move_aside(original.path(), Path::new("a"), &directory).unwrap();
+
+        std::fs::write(original.path().join("a"), "second").unwrap();
+        let err = move_aside(original.path(), Path::new("a"), &directory).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            std::fs::read_to_string(directory.join("a")).unwrap(),
+            "first"
+        );
+        assert_eq!(
+            std::fs::read_to_string(original.path().join("a")).unwrap(),
+            "second"
+        );
+    }
+
+    #[test]
+    fn each_run_gets_its_own_conflicts_dir() {
+        let version: Version = serde_yaml::from_str("{name: v0, determinants: []}").unwrap();
+        let first = conflicts_dir(Path::new("game"), &version);
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let second = conflicts_dir(Path::new("game"), &version);
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), second.parent());
+    }
+}
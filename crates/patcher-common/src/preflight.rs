@@ -0,0 +1,158 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use tar::Archive;
+
+use crate::error::DownloadAndPatchError;
+
+#[derive(Debug)]
+pub struct BlockedFile {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+///
+/// Lists the files contained in a patch archive, relative to the install directory
+///
+pub fn archive_files(archive: &mut Archive<impl Read>) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() {
+            files.push(entry.path()?.into_owned());
+        }
+    }
+    Ok(files)
+}
+
+fn is_read_only(path: &Path) -> io::Result<bool> {
+    Ok(path.metadata()?.permissions().readonly())
+}
+
+fn make_writable(path: &Path) -> io::Result<()> {
+    let mut permissions = path.metadata()?.permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(path, permissions)
+}
+
+///
+/// Checks that every file of `files` existing in `original` can be replaced, reporting all the
+/// blocked files at once.
+///
+/// Files only blocked by a read-only attribute are made writable, but only once every other file
+/// is known to be writable, so nothing is changed when the check fails.
+///
+pub fn check_writable(original: &Path, files: &[PathBuf]) -> Result<(), DownloadAndPatchError> {
+    let mut blocked = Vec::new();
+    let mut read_only = Vec::new();
+    for file in files {
+        let path = original.join(file);
+        if !path.exists() {
+            continue;
+        }
+        let res = is_read_only(&path).and_then(|is_read_only| {
+            if is_read_only {
+                read_only.push(path.clone());
+                Ok(())
+            } else {
+                OpenOptions::new().write(true).open(&path).map(drop)
+            }
+        });
+        if let Err(error) = res {
+            log::warn!("`{}` cannot be written: {error}", path.display());
+            blocked.push(BlockedFile { path, error });
+        }
+    }
+
+    if !blocked.is_empty() {
+        return Err(DownloadAndPatchError::BlockedFiles(blocked));
+    }
+
+    for path in read_only {
+        log::info!("removing read-only attribute of `{}`", path.display());
+        make_writable(&path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_files_lists_files_only() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        builder
+            .append_data(&mut header, "data/", io::empty())
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        builder
+            .append_data(&mut header, "data/a", &b"hello"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let files = archive_files(&mut Archive::new(&archive[..])).unwrap();
+        assert_eq!(files, [PathBuf::from("data/a")]);
+    }
+
+    #[test]
+    fn missing_and_writable_files_pass() {
+        let original = tempfile::tempdir().unwrap();
+        std::fs::write(original.path().join("a"), "a").unwrap();
+        let files = [PathBuf::from("a"), PathBuf::from("missing")];
+        assert!(check_writable(original.path(), &files).is_ok());
+    }
+
+    #[test]
+    fn read_only_files_are_made_writable() {
+        let original = tempfile::tempdir().unwrap();
+        let path = original.path().join("a");
+        std::fs::write(&path, "a").unwrap();
+        let mut permissions = path.metadata().unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+
+        check_writable(original.path(), &[PathBuf::from("a")]).unwrap();
+        assert!(!is_read_only(&path).unwrap());
+    }
+
+    #[test]
+    fn blocked_files_are_all_reported_before_changing_anything() {
+        let original = tempfile::tempdir().unwrap();
+        // A directory cannot be opened for writing, whatever the permissions of the user
+        std::fs::create_dir(original.path().join("b")).unwrap();
+        std::fs::create_dir(original.path().join("c")).unwrap();
+        let read_only = original.path().join("a");
+        std::fs::write(&read_only, "a").unwrap();
+        let mut permissions = read_only.metadata().unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&read_only, permissions).unwrap();
+
+        let files = ["a", "b", "c"].map(PathBuf::from);
+        match check_writable(original.path(), &files) {
+            Err(DownloadAndPatchError::BlockedFiles(blocked)) => {
+                let paths: Vec<_> = blocked.iter().map(|x| x.path.clone()).collect();
+                assert_eq!(
+                    paths,
+                    [original.path().join("b"), original.path().join("c")]
+                );
+            }
+            res => panic!("unexpected result: {res:?}"),
+        }
+        assert!(is_read_only(&read_only).unwrap());
+    }
+}
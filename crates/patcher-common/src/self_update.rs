@@ -0,0 +1,274 @@
+use std::{
+    cmp::Ordering,
+    ffi::OsString,
+    fs::File,
+    io,
+    num::ParseIntError,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use thl_patcher::CancellationToken;
+
+use crate::{download::download, error::SelfUpdateError};
+
+/// Files read or written by the patcher in the current directory
+const PATCHER_FILES: [&str; 3] = ["config.yaml", "log4rs.yaml", "logs.txt"];
+
+///
+/// The latest release of the patcher itself, declared in the source
+///
+#[derive(Debug, Deserialize, Clone)]
+pub struct PatcherRelease {
+    pub version: String,
+    pub downloads: Vec<PatcherDownload>,
+}
+
+///
+/// A single executable of a [`PatcherRelease`]
+///
+#[derive(Debug, Deserialize, Clone)]
+pub struct PatcherDownload {
+    /// The frontend this executable is, like `patcher-terminal` or `patcher-gui`
+    pub executable: String,
+    /// The operating system this executable runs on, named like [`std::env::consts::OS`]
+    pub target_os: String,
+    /// The architecture this executable runs on, named like [`std::env::consts::ARCH`], all of
+    /// them when `None`
+    pub target_arch: Option<String>,
+    pub link: String,
+    pub sha256: String,
+}
+
+impl PatcherRelease {
+    ///
+    /// Gets the download replacing `executable`, running at `current_version` on this platform,
+    /// if this release is newer
+    ///
+    pub fn update_for(&self, executable: &str, current_version: &str) -> Option<&PatcherDownload> {
+        if !is_newer(&self.version, current_version) {
+            return None;
+        }
+        self.downloads.iter().find(|x| {
+            x.executable == executable
+                && x.target_os == std::env::consts::OS
+                && x.target_arch
+                    .as_ref()
+                    .is_none_or(|arch| arch == std::env::consts::ARCH)
+        })
+    }
+}
+
+///
+/// Compares versions like `1.2.0`, ignoring a leading `v` and any build metadata after a `+`.
+/// Missing components count as zeros, so `1.2` and `1.2.0` are the same version. A pre-release
+/// like `1.2.0-beta.1` is older than the release `1.2.0`. Versions that can't be parsed are
+/// considered newer as soon as they differ.
+///
+fn is_newer(candidate: &str, current: &str) -> bool {
+    fn parse(version: &str) -> Result<(Vec<u64>, Option<&str>), ParseIntError> {
+        let version = version
+            .trim_start_matches('v')
+            .split('+')
+            .next()
+            .unwrap_or_default();
+        let (release, pre_release) = match version.split_once('-') {
+            Some((release, pre_release)) => (release, Some(pre_release)),
+            None => (version, None),
+        };
+        let release = release
+            .split('.')
+            .map(str::parse::<u64>)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((release, pre_release))
+    }
+    match (parse(candidate), parse(current)) {
+        (Ok((mut candidate, candidate_pre)), Ok((mut current, current_pre))) => {
+            let len = candidate.len().max(current.len());
+            candidate.resize(len, 0);
+            current.resize(len, 0);
+            candidate
+                .cmp(&current)
+                .then_with(|| compare_pre_releases(candidate_pre, current_pre))
+                .is_gt()
+        }
+        _ => candidate != current,
+    }
+}
+
+///
+/// Compares pre-release suffixes like semver does: a release is newer than any of its
+/// pre-releases, and their dot-separated identifiers are compared one by one, numerically when
+/// they are numbers
+///
+fn compare_pre_releases(a: Option<&str>, b: Option<&str>) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => {
+            // `Ok` is lower than `Err`, so numeric identifiers are older than alphanumeric ones
+            let key = |identifier| str::parse::<u64>(identifier).map_err(|_| identifier);
+            a.split('.').map(key).cmp(b.split('.').map(key))
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path);
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+///
+/// Replaces the running executable with `download`, checking its SHA-256 first. The previous
+/// executable is kept next to the new one until [`remove_previous_executable`] is called, as it
+/// can't be deleted while running on some platforms. Returns the path of the new executable.
+///
+pub fn self_update(
+    release: &PatcherDownload,
+    cancel: &CancellationToken,
+) -> Result<PathBuf, SelfUpdateError> {
+    let current = std::env::current_exe()?;
+    let dir = current
+        .parent()
+        .ok_or_else(|| io::Error::other("the executable has no parent directory"))?;
+    let (mut downloaded, hash) = download(&release.link, dir, cancel)?;
+    if hash != release.sha256 {
+        return Err(SelfUpdateError::ChecksumMismatch {
+            expected: release.sha256.clone(),
+            found: hash,
+        });
+    }
+
+    let new = with_suffix(&current, ".new");
+    io::copy(&mut downloaded, &mut File::create(&new)?)?;
+    std::fs::set_permissions(&new, std::fs::metadata(&current)?.permissions())?;
+
+    let previous = with_suffix(&current, ".old");
+    if previous.exists() {
+        std::fs::remove_file(&previous)?;
+    }
+    std::fs::rename(&current, &previous)?;
+    if let Err(e) = std::fs::rename(&new, &current) {
+        // Puts the running executable back, so that the patcher can still be started
+        std::fs::rename(&previous, &current)?;
+        return Err(e.into());
+    }
+    log::info!("patcher replaced by the one at {}", release.link);
+    Ok(current)
+}
+
+///
+/// Starts `executable` with the arguments of the running patcher. On unix, the running process
+/// is replaced and this only returns on failure. Elsewhere, the new process is spawned, and the
+/// caller is expected to exit.
+///
+pub fn relaunch(executable: &Path) -> io::Result<()> {
+    let mut command = std::process::Command::new(executable);
+    command.args(std::env::args_os().skip(1));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Err(command.exec())
+    }
+    #[cfg(not(unix))]
+    {
+        command.spawn().map(|_| ())
+    }
+}
+
+///
+/// Lists the files of the patcher inside `original`, relative to it, when the patcher is run
+/// from the install directory: its executable, the one left by [`self_update`], and the files it
+/// reads or writes in the current directory
+///
+pub(crate) fn patcher_files(original: &Path) -> Vec<PathBuf> {
+    let Ok(original) = original.canonicalize() else {
+        return Vec::new();
+    };
+    let mut files = Vec::new();
+    if let Ok(current) = std::env::current_exe() {
+        files.push(with_suffix(&current, ".old"));
+        files.push(current);
+    }
+    if let Ok(dir) = std::env::current_dir() {
+        files.extend(PATCHER_FILES.iter().map(|x| dir.join(x)));
+    }
+    files
+        .into_iter()
+        .filter_map(|x| {
+            let file = x.canonicalize().ok()?;
+            file.strip_prefix(&original).ok().map(Path::to_path_buf)
+        })
+        .collect()
+}
+
+///
+/// Removes the executable left behind by a previous [`self_update`]
+///
+pub fn remove_previous_executable() {
+    let Ok(current) = std::env::current_exe() else {
+        return;
+    };
+    let previous = with_suffix(&current, ".old");
+    match std::fs::remove_file(&previous) {
+        Ok(()) => log::debug!("removed previous executable `{}`", previous.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => log::warn!("couldn't remove previous executable: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newer_versions() {
+        assert!(is_newer("0.2.0", "0.1.0"));
+        assert!(is_newer("1.0.0", "0.9.9"));
+        assert!(is_newer("0.10.0", "0.9.0"));
+        assert!(is_newer("v1.2.1", "1.2.0"));
+        assert!(!is_newer("0.1.0", "0.2.0"));
+        assert!(!is_newer("0.2.0", "0.2.0"));
+    }
+
+    #[test]
+    fn missing_components_are_zeros() {
+        assert!(!is_newer("1.2.0", "1.2"));
+        assert!(!is_newer("1.2", "1.2.0"));
+        assert!(is_newer("1.2.1", "1.2"));
+        assert!(!is_newer("1.2", "1.2.1"));
+        assert!(is_newer("2", "1.9.9"));
+    }
+
+    #[test]
+    fn pre_releases_are_older() {
+        assert!(!is_newer("0.2.0-beta.1", "0.2.0"));
+        assert!(is_newer("0.2.0", "0.2.0-beta.1"));
+        assert!(is_newer("0.2.0", "0.2.0-rc.1"));
+        assert!(is_newer("0.3.0-rc.1", "0.2.0"));
+        assert!(!is_newer("0.2.0-rc.1", "0.2.0-rc.1"));
+    }
+
+    #[test]
+    fn pre_releases_are_ordered() {
+        assert!(is_newer("0.2.0-rc.1", "0.2.0-beta.1"));
+        assert!(is_newer("0.2.0-beta.11", "0.2.0-beta.2"));
+        assert!(is_newer("0.2.0-beta.1", "0.2.0-beta"));
+        assert!(is_newer("0.2.0-alpha", "0.2.0-1"));
+    }
+
+    #[test]
+    fn build_metadata_is_ignored() {
+        assert!(!is_newer("0.2.0+build.5", "0.2.0"));
+        assert!(!is_newer("0.2.0", "0.2.0+build.5"));
+        assert!(!is_newer("0.2.0-beta.1+build.5", "0.2.0-beta.1"));
+    }
+
+    #[test]
+    fn unparsable_versions() {
+        assert!(is_newer("nightly", "0.2.0"));
+        assert!(!is_newer("nightly", "nightly"));
+    }
+}
@@ -0,0 +1,44 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::download::PATCHER_DIR;
+
+const SETTINGS_FILE: &str = "settings.yaml";
+
+///
+/// Choices made by the user for an install directory, kept between runs
+///
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Settings {
+    /// The channel followed by the install, overriding the one of the config
+    pub channel: Option<String>,
+}
+
+fn settings_path(original: &Path) -> PathBuf {
+    original.join(PATCHER_DIR).join(SETTINGS_FILE)
+}
+
+impl Settings {
+    pub fn read(original: &Path) -> Self {
+        let Ok(content) = std::fs::read_to_string(settings_path(original)) else {
+            return Self::default();
+        };
+        serde_yaml::from_str(&content).unwrap_or_else(|e| {
+            log::warn!("invalid settings file: {e}");
+            Self::default()
+        })
+    }
+
+    pub fn write(&self, original: &Path) -> io::Result<()> {
+        let content = serde_yaml::to_string(self).map_err(io::Error::other)?;
+        let path = settings_path(original);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, content)
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{download::PATCHER_DIR, structures::source::Version};
+
+const STATE_FILE: &str = "state.yaml";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeterminantState {
+    pub file: String,
+    /// The hash of the determinant, used to tell whether it changed in the source
+    #[serde(alias = "sha256")]
+    pub hash: Option<String>,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl DeterminantState {
+    fn matches_file(&self, path: &Path) -> bool {
+        let Ok(metadata) = path.metadata() else {
+            return false;
+        };
+        metadata.len() == self.size && metadata.modified().ok() == self.modified
+    }
+}
+
+///
+/// The version installed by the last successful update, along with what its determinants looked
+/// like at that time. It allows detecting the current version without hashing anything, as long
+/// as the determinants weren't touched since.
+///
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InstalledState {
+    pub version: String,
+    pub determinants: Vec<DeterminantState>,
+}
+
+///
+/// Each component has its own state file, next to the one of the main versions
+///
+fn state_path(original: &Path, component: Option<&str>) -> PathBuf {
+    let file = match component {
+        Some(component) => format!("state-{component}.yaml"),
+        None => STATE_FILE.to_string(),
+    };
+    original.join(PATCHER_DIR).join(file)
+}
+
+impl InstalledState {
+    pub fn read(original: &Path, component: Option<&str>) -> Option<Self> {
+        let content = std::fs::read_to_string(state_path(original, component)).ok()?;
+        match serde_yaml::from_str(&content) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                log::warn!("invalid state file: {e}");
+                None
+            }
+        }
+    }
+
+    ///
+    /// Whether the determinants of `version` are the ones recorded, and look untouched in
+    /// `original`
+    ///
+    pub fn matches(&self, original: &Path, version: &Version) -> bool {
+        version.name == self.version
+            && version.determinants.iter().all(|determinant| {
+                // Checking that a file is missing is as cheap as checking the state
+                if determinant.absent {
+                    return determinant.matches_metadata(original);
+                }
+                self.determinants.iter().any(|state| {
+                    state.file == determinant.file
+                        && state.hash.as_deref() == determinant.hash().map(|(_, hash)| hash)
+                        && state.matches_file(&original.join(&state.file))
+                })
+            })
+    }
+
+    ///
+    /// Checks the determinants of `version` in `original`, and records them in the state file if
+    /// they match. Otherwise, any previous state file is removed.
+    ///
+    pub fn write(original: &Path, version: &Version) -> io::Result<()> {
+        let mut determinants = Vec::with_capacity(version.determinants.len());
+        for determinant in &version.determinants {
+            if !determinant.matches(original)? {
+                log::warn!(
+                    "`{}` doesn't match version `{}`, not writing the state file",
+                    determinant.file,
+                    version.name
+                );
+                return Self::remove(original, version.component.as_deref());
+            }
+            if determinant.absent {
+                continue;
+            }
+            let metadata = std::fs::metadata(original.join(&determinant.file))?;
+            determinants.push(DeterminantState {
+                file: determinant.file.clone(),
+                hash: determinant.hash().map(|(_, hash)| hash.to_string()),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+
+        let state = Self {
+            version: version.name.clone(),
+            determinants,
+        };
+        let content = serde_yaml::to_string(&state).map_err(io::Error::other)?;
+        let path = state_path(original, version.component.as_deref());
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, content)
+    }
+
+    pub fn remove(original: &Path, component: Option<&str>) -> io::Result<()> {
+        match std::fs::remove_file(state_path(original, component)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA-256 of `hello`
+    const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn version(yaml: &str) -> Version {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn v1() -> Version {
+        version(&format!(
+            "
+name: v1
+determinants:
+  - file: a
+    sha256: {HELLO}
+  - file: b
+    absent: true
+"
+        ))
+    }
+
+    #[test]
+    fn written_state_matches_the_version() {
+        let original = tempfile::tempdir().unwrap();
+        std::fs::write(original.path().join("a"), "hello").unwrap();
+        InstalledState::write(original.path(), &v1()).unwrap();
+
+        let state = InstalledState::read(original.path(), None).unwrap();
+        assert_eq!(state.version, "v1");
+        assert_eq!(state.determinants.len(), 1);
+        assert_eq!(state.determinants[0].hash.as_deref(), Some(HELLO));
+        assert!(state.matches(original.path(), &v1()));
+        assert!(InstalledState::read(original.path(), Some("voices")).is_none());
+    }
+
+    #[test]
+    fn touched_determinants_dont_match() {
+        let original = tempfile::tempdir().unwrap();
+        std::fs::write(original.path().join("a"), "hello").unwrap();
+        InstalledState::write(original.path(), &v1()).unwrap();
+        let state = InstalledState::read(original.path(), None).unwrap();
+
+        std::fs::write(original.path().join("b"), "").unwrap();
+        assert!(!state.matches(original.path(), &v1()));
+        std::fs::remove_file(original.path().join("b")).unwrap();
+
+        std::fs::write(original.path().join("a"), "hello, world").unwrap();
+        assert!(!state.matches(original.path(), &v1()));
+    }
+
+    #[test]
+    fn other_versions_dont_match() {
+        let original = tempfile::tempdir().unwrap();
+        std::fs::write(original.path().join("a"), "hello").unwrap();
+        InstalledState::write(original.path(), &v1()).unwrap();
+        let state = InstalledState::read(original.path(), None).unwrap();
+
+        let v2 = version(&format!(
+            "
+name: v2
+determinants:
+  - file: a
+    sha256: {HELLO}
+"
+        ));
+        assert!(!state.matches(original.path(), &v2));
+    }
+
+    #[test]
+    fn mismatching_install_removes_the_state() {
+        let original = tempfile::tempdir().unwrap();
+        std::fs::write(original.path().join("a"), "hello").unwrap();
+        InstalledState::write(original.path(), &v1()).unwrap();
+
+        std::fs::write(original.path().join("a"), "hello, world").unwrap();
+        InstalledState::write(original.path(), &v1()).unwrap();
+        assert!(InstalledState::read(original.path(), None).is_none());
+    }
+
+    #[test]
+    fn components_have_their_own_state() {
+        let original = tempfile::tempdir().unwrap();
+        std::fs::write(original.path().join("a"), "hello").unwrap();
+        let mut component = v1();
+        component.component = Some("voices".to_string());
+        InstalledState::write(original.path(), &component).unwrap();
+
+        assert!(InstalledState::read(original.path(), None).is_none());
+        let state = InstalledState::read(original.path(), Some("voices")).unwrap();
+        assert!(state.matches(original.path(), &component));
+    }
+}
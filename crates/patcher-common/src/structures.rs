@@ -1,2 +1,7 @@
+pub mod channel;
+pub mod component;
 pub mod config;
+pub mod planner;
+pub mod platform;
 pub mod source;
+pub mod validation;
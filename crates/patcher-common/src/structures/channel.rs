@@ -0,0 +1,181 @@
+use serde::Deserialize;
+
+use super::source::{PatchLink, Source, Version};
+use crate::error::ChannelError;
+
+///
+/// The name of the channel made of the top-level versions of the source
+///
+pub const DEFAULT_CHANNEL: &str = "stable";
+
+///
+/// A version chain branching off the default one, sharing its history up to `branch`. A channel
+/// whose archive differs between platforms is declared once for each of them, with the same name.
+///
+#[derive(Debug, Deserialize, Clone)]
+pub struct Channel {
+    pub name: String,
+    /// The version of the default channel this channel starts from
+    pub branch: String,
+    /// The archive going from `branch` to the first version of this channel
+    #[serde(flatten)]
+    pub patch: PatchLink,
+    pub versions: Vec<Version>,
+    /// The operating system this channel is meant for, all of them when `None`
+    pub target_os: Option<String>,
+    /// The architecture this channel is meant for, all of them when `None`
+    pub target_arch: Option<String>,
+}
+
+impl Source {
+    ///
+    /// The names of all the channels of this source, starting with the default one
+    ///
+    pub fn channel_names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(DEFAULT_CHANNEL).chain(self.channels.iter().map(|x| x.name.as_str()))
+    }
+
+    ///
+    /// Gets the single version chain followed by the channel `name`: the default versions up to
+    /// the branch point, then the versions of the channel. `None` gets the default channel.
+    ///
+    pub fn channel(&self, name: Option<&str>) -> Result<Self, ChannelError> {
+        let name = name.unwrap_or(DEFAULT_CHANNEL);
+        if name == DEFAULT_CHANNEL {
+            return Ok(Self {
+                versions: self.versions.clone(),
+                channels: Vec::new(),
+                components: self.components.clone(),
+                patcher: self.patcher.clone(),
+            });
+        }
+        let channel = self
+            .channels
+            .iter()
+            .find(|x| x.name == name)
+            .ok_or_else(|| ChannelError::Unknown(name.to_string()))?;
+        let branch =
+            self.find_version(&channel.branch)
+                .ok_or_else(|| ChannelError::BranchNotFound {
+                    channel: channel.name.clone(),
+                    branch: channel.branch.clone(),
+                })?;
+
+        let mut versions = self.versions[..=branch].to_vec();
+        let branch_version = &mut versions[branch];
+        branch_version.update_link = Some(channel.patch.link.clone());
+        branch_version.archive_size = channel.patch.archive_size;
+        branch_version.unpacked_size = channel.patch.unpacked_size;
+        versions.extend(channel.versions.iter().cloned());
+
+        // Jumps of the shared history to later versions of the default channel are dropped
+        let names: Vec<String> = versions.iter().map(|x| x.name.clone()).collect();
+        for version in &mut versions {
+            version.jumps.retain(|jump| names.contains(&jump.to));
+        }
+        Ok(Self {
+            versions,
+            channels: Vec::new(),
+            components: self.components.clone(),
+            patcher: self.patcher.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "
+versions:
+  - name: v0
+    update_link: v0-v1
+    jumps:
+      - to: v2
+        link: v0-v2
+      - to: v1
+        link: v0-v1-jump
+    determinants:
+      - file: a
+  - name: v1
+    update_link: v1-v2
+    determinants:
+      - file: b
+  - name: v2
+    determinants:
+      - file: c
+channels:
+  - name: beta
+    branch: v1
+    link: v1-beta.1
+    archive_size: 10
+    unpacked_size: 20
+    versions:
+      - name: beta.1
+        update_link: beta.1-beta.2
+        determinants:
+          - file: d
+      - name: beta.2
+        determinants:
+          - file: e
+";
+
+    fn source() -> Source {
+        serde_yaml::from_str(SOURCE).unwrap()
+    }
+
+    fn names(source: &Source) -> Vec<&str> {
+        source.versions.iter().map(|x| x.name.as_str()).collect()
+    }
+
+    #[test]
+    fn default_channel_is_the_top_level_chain() {
+        let source = source();
+        assert_eq!(names(&source.channel(None).unwrap()), ["v0", "v1", "v2"]);
+        let stable = source.channel(Some(DEFAULT_CHANNEL)).unwrap();
+        assert_eq!(names(&stable), ["v0", "v1", "v2"]);
+        assert!(stable.channels.is_empty());
+    }
+
+    #[test]
+    fn channel_is_spliced_at_its_branch() {
+        let beta = source().channel(Some("beta")).unwrap();
+        assert_eq!(names(&beta), ["v0", "v1", "beta.1", "beta.2"]);
+        let branch = &beta.versions[1];
+        assert_eq!(branch.update_link.as_deref(), Some("v1-beta.1"));
+        assert_eq!(branch.archive_size, Some(10));
+        assert_eq!(branch.unpacked_size, Some(20));
+        assert_eq!(beta.versions[0].update_link.as_deref(), Some("v0-v1"));
+    }
+
+    #[test]
+    fn jumps_out_of_the_channel_are_dropped() {
+        let beta = source().channel(Some("beta")).unwrap();
+        let jumps: Vec<_> = beta.versions[0].jumps.iter().map(|x| &x.to).collect();
+        assert_eq!(jumps, ["v1"]);
+    }
+
+    #[test]
+    fn unknown_channels_and_branches() {
+        let mut source = source();
+        assert_eq!(
+            source.channel(Some("nightly")).unwrap_err(),
+            ChannelError::Unknown("nightly".to_string())
+        );
+        source.channels[0].branch = "v9".to_string();
+        assert_eq!(
+            source.channel(Some("beta")).unwrap_err(),
+            ChannelError::BranchNotFound {
+                channel: "beta".to_string(),
+                branch: "v9".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn channel_names_start_with_the_default_one() {
+        let source = source();
+        let names: Vec<_> = source.channel_names().collect();
+        assert_eq!(names, [DEFAULT_CHANNEL, "beta"]);
+    }
+}
@@ -0,0 +1,66 @@
+use std::{io, path::Path};
+
+use serde::Deserialize;
+
+use super::source::{Source, Version};
+use crate::error::ComponentError;
+
+///
+/// An optional part of the install, like a language pack, with its own version chain. Its
+/// determinants and patches apply on the same install directory as the main versions.
+///
+#[derive(Debug, Deserialize, Clone)]
+pub struct Component {
+    pub name: String,
+    /// Shown to the user next to the name of the component
+    pub description: Option<String>,
+    pub versions: Vec<Version>,
+}
+
+impl Source {
+    ///
+    /// Gets the version chain of the component `name`, which can be detected and updated like
+    /// the main one. Its state, history entries and backups are kept apart from the main ones.
+    ///
+    pub fn component(&self, name: &str) -> Result<Self, ComponentError> {
+        let component = self
+            .components
+            .iter()
+            .find(|x| x.name == name)
+            .ok_or_else(|| ComponentError::Unknown(name.to_string()))?;
+        let mut versions = component.versions.clone();
+        for version in &mut versions {
+            version.component = Some(component.name.clone());
+        }
+        Ok(Self {
+            versions,
+            channels: Vec::new(),
+            components: Vec::new(),
+            patcher: None,
+        })
+    }
+
+    ///
+    /// Gets the version installed in `original` of each component, skipping the ones that aren't
+    /// installed
+    ///
+    pub fn installed_components(&self, original: &Path) -> io::Result<Vec<Version>> {
+        let mut installed = Vec::new();
+        for component in &self.components {
+            let Ok(chain) = self.component(&component.name) else {
+                continue;
+            };
+            if let Some(i) = chain.get_current_version(original)? {
+                installed.push(chain.versions[i].clone());
+            }
+        }
+        Ok(installed)
+    }
+
+    ///
+    /// The component this version chain belongs to, `None` for the main one
+    ///
+    pub fn component_name(&self) -> Option<&str> {
+        self.versions.first()?.component.as_deref()
+    }
+}
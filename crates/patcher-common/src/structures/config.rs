@@ -2,6 +2,8 @@ use std::path::Path;
 
 use serde::Deserialize;
 
+use crate::settings::Settings;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct DefaultPaths {
     pub target_os: String,
@@ -13,6 +15,11 @@ pub struct PatcherConfig {
     pub window_name: String,
     pub source: String,
     pub default_paths: Vec<DefaultPaths>,
+    /// Whether the files replaced by an update are kept, allowing to roll it back
+    #[serde(default)]
+    pub keep_backups: bool,
+    /// The channel followed when none was chosen for an install directory
+    pub channel: Option<String>,
 }
 
 impl Default for PatcherConfig {
@@ -21,11 +28,23 @@ impl Default for PatcherConfig {
             window_name: String::from("Patcher"),
             default_paths: vec![],
             source: String::new(),
+            keep_backups: false,
+            channel: None,
         }
     }
 }
 
 impl PatcherConfig {
+    ///
+    /// The channel followed by the install directory `original`: the one chosen by the user if
+    /// any, otherwise the one of this config
+    ///
+    pub fn channel_for(&self, original: &Path) -> Option<String> {
+        Settings::read(original)
+            .channel
+            .or_else(|| self.channel.clone())
+    }
+
     pub fn get_default_path(&self) -> Option<String> {
         for entry in self
             .default_paths
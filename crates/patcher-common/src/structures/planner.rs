@@ -0,0 +1,466 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use super::source::{PatchLinkRef, Source, Version, VersionTransitionRef};
+use crate::error::PlanError;
+
+///
+/// The cost of a path, compared field by field: paths going through archives of unknown size
+/// are avoided first, then the smallest total download wins, then the shortest path
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+struct Cost {
+    unknown_sizes: usize,
+    archive_size: u64,
+    steps: usize,
+}
+
+impl Cost {
+    fn add(self, patch: PatchLinkRef) -> Self {
+        Self {
+            unknown_sizes: self.unknown_sizes + usize::from(patch.archive_size.is_none()),
+            archive_size: self.archive_size + patch.archive_size.unwrap_or_default(),
+            steps: self.steps + 1,
+        }
+    }
+}
+
+impl Source {
+    ///
+    /// All the archives that can be applied on the version `from`: its update link, its
+    /// downgrade link, then its jumps
+    ///
+    pub fn edges(&self, from: usize) -> impl Iterator<Item = (usize, PatchLinkRef<'_>)> {
+        let version = self.versions.get(from);
+        let update = version
+            .and_then(|version| version.update_patch())
+            .filter(|_| from + 1 < self.versions.len())
+            .map(|patch| (from + 1, patch));
+        let downgrade = version
+            .and_then(|version| version.downgrade.as_ref())
+            .zip(from.checked_sub(1))
+            .map(|(patch, to)| (to, patch.as_ref()));
+        let jumps = version
+            .into_iter()
+            .flat_map(|version| &version.jumps)
+            .filter_map(move |jump| {
+                let Some(to) = self.versions.iter().position(|x| x.name == jump.to) else {
+                    log::warn!("jump to unknown version `{}`", jump.to);
+                    return None;
+                };
+                Some((to, jump.patch.as_ref()))
+            });
+        update.into_iter().chain(downgrade).chain(jumps)
+    }
+
+    ///
+    /// Gets the cheapest sequence of transitions going from `current` to `target`, by declared
+    /// archive size, or `None` if `target` cannot be reached
+    ///
+    pub fn plan(&self, current: usize, target: usize) -> Option<Vec<VersionTransitionRef<'_>>> {
+        if current == target && current < self.versions.len() {
+            return Some(Vec::new());
+        }
+        let costs = self.costs_from(current);
+        costs.get(target)?.as_ref()?;
+
+        let mut transitions = Vec::new();
+        let mut to = target;
+        while to != current {
+            let (_, from, patch) = costs[to]?;
+            transitions.push(VersionTransitionRef {
+                old: &self.versions[from],
+                new: &self.versions[to],
+                patch: Some(patch),
+            });
+            to = from;
+        }
+        transitions.reverse();
+        Some(transitions)
+    }
+
+    ///
+    /// Gets the cheapest sequence of transitions going from `current` to the version named
+    /// `target`, checking that it exists and can be reached
+    ///
+    pub fn plan_to(
+        &self,
+        current: usize,
+        target: &str,
+    ) -> Result<Vec<VersionTransitionRef<'_>>, PlanError> {
+        let index = self
+            .find_version(target)
+            .ok_or_else(|| PlanError::UnknownVersion(target.to_string()))?;
+        self.plan(current, index)
+            .ok_or_else(|| PlanError::Unreachable {
+                from: self
+                    .versions
+                    .get(current)
+                    .map(|x| x.name.clone())
+                    .unwrap_or_default(),
+                to: target.to_string(),
+            })
+    }
+
+    ///
+    /// Gets the index of the last version named `name`
+    ///
+    pub fn find_version(&self, name: &str) -> Option<usize> {
+        self.versions.iter().rposition(|x| x.name == name)
+    }
+
+    ///
+    /// Gets every version later than `current` that can be reached from it, in order
+    ///
+    pub fn reachable_versions(&self, current: usize) -> Vec<usize> {
+        self.costs_from(current)
+            .iter()
+            .enumerate()
+            .filter(|(i, cost)| *i > current && cost.is_some())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    ///
+    /// Gets every version older than `current` that can be reached from it through downgrade
+    /// links, in order
+    ///
+    pub fn reachable_older_versions(&self, current: usize) -> Vec<usize> {
+        self.costs_from(current)
+            .iter()
+            .enumerate()
+            .filter(|(i, cost)| *i < current && cost.is_some())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    ///
+    /// Gets the versions brought by an update from `current` to `target`, including the ones
+    /// skipped by jumps, so that all their changelogs can be shown
+    ///
+    pub fn changes(&self, current: usize, target: usize) -> &[Version] {
+        self.versions.get(current + 1..=target).unwrap_or_default()
+    }
+
+    ///
+    /// Gets the cheapest sequence of transitions going from `current` to the most recent version
+    /// reachable from it
+    ///
+    pub fn plan_to_latest(&self, current: usize) -> Vec<VersionTransitionRef<'_>> {
+        let latest = self
+            .reachable_versions(current)
+            .last()
+            .copied()
+            .unwrap_or(current);
+        self.plan(current, latest).unwrap_or_default()
+    }
+
+    ///
+    /// Runs Dijkstra from `current`, returning for each version its cost, the version it's
+    /// reached from, and the archive used to do so. `current` itself has no predecessor.
+    ///
+    #[allow(clippy::type_complexity)]
+    fn costs_from(&self, current: usize) -> Vec<Option<(Cost, usize, PatchLinkRef<'_>)>> {
+        let mut best: Vec<Option<(Cost, usize, PatchLinkRef)>> = vec![None; self.versions.len()];
+        let mut visited = vec![false; self.versions.len()];
+        if current >= self.versions.len() {
+            return best;
+        }
+        let mut queue = BinaryHeap::from([Reverse((Cost::default(), current))]);
+        while let Some(Reverse((cost, from))) = queue.pop() {
+            if std::mem::replace(&mut visited[from], true) {
+                continue;
+            }
+            for (to, patch) in self.edges(from) {
+                let new_cost = cost.add(patch);
+                if to != current && best[to].is_none_or(|(cost, ..)| new_cost < cost) {
+                    best[to] = Some((new_cost, from, patch));
+                    queue.push(Reverse((new_cost, to)));
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(yaml: &str) -> Source {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn path(source: &Source, current: usize, target: usize) -> Option<Vec<&str>> {
+        let transitions = source.plan(current, target)?;
+        let mut names = vec![source.versions[current].name.as_str()];
+        names.extend(transitions.iter().map(|x| x.new.name.as_str()));
+        Some(names)
+    }
+
+    const CHAIN: &str = "
+versions:
+  - name: v0
+    update_link: v0-v1
+    archive_size: 10
+    determinants: []
+  - name: v1
+    update_link: v1-v2
+    archive_size: 10
+    determinants: []
+  - name: v2
+    update_link: v2-v3
+    archive_size: 10
+    determinants: []
+  - name: v3
+    determinants: []
+";
+
+    #[test]
+    fn follows_update_links() {
+        let source = source(CHAIN);
+        assert_eq!(path(&source, 0, 3).unwrap(), ["v0", "v1", "v2", "v3"]);
+        assert_eq!(path(&source, 1, 2).unwrap(), ["v1", "v2"]);
+        let transitions = source.plan(0, 1).unwrap();
+        assert_eq!(transitions[0].patch.unwrap().link, "v0-v1");
+    }
+
+    #[test]
+    fn same_version_needs_no_transition() {
+        let source = source(CHAIN);
+        assert!(source.plan(2, 2).unwrap().is_empty());
+        assert!(source.plan(4, 4).is_none());
+    }
+
+    #[test]
+    fn older_versions_need_downgrade_links() {
+        let source = source(CHAIN);
+        assert!(source.plan(2, 0).is_none());
+        assert!(source.plan(0, 4).is_none());
+    }
+
+    #[test]
+    fn follows_downgrade_links() {
+        let source = source(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    determinants: []
+  - name: v1
+    update_link: v1-v2
+    downgrade:
+      link: v1-v0
+    determinants: []
+  - name: v2
+    downgrade:
+      link: v2-v1
+    determinants: []
+",
+        );
+        assert_eq!(path(&source, 2, 0).unwrap(), ["v2", "v1", "v0"]);
+        let links: Vec<_> = source
+            .plan(2, 0)
+            .unwrap()
+            .iter()
+            .map(|x| x.patch.unwrap().link)
+            .collect();
+        assert_eq!(links, ["v2-v1", "v1-v0"]);
+        // Downgrades don't count as updates
+        assert!(source.reachable_versions(2).is_empty());
+        assert_eq!(source.reachable_older_versions(2), [0, 1]);
+        assert!(source.reachable_older_versions(0).is_empty());
+    }
+
+    #[test]
+    fn missing_update_link_cuts_the_chain() {
+        let source = source(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    determinants: []
+  - name: v1
+    determinants: []
+  - name: v2
+    determinants: []
+",
+        );
+        assert!(source.plan(0, 1).is_some());
+        assert!(source.plan(0, 2).is_none());
+        assert_eq!(source.reachable_versions(0), [1]);
+        assert!(source.reachable_versions(1).is_empty());
+    }
+
+    #[test]
+    fn cheaper_jump_is_taken() {
+        let source = source(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    archive_size: 10
+    jumps:
+      - to: v2
+        link: v0-v2
+        archive_size: 15
+    determinants: []
+  - name: v1
+    update_link: v1-v2
+    archive_size: 10
+    determinants: []
+  - name: v2
+    determinants: []
+",
+        );
+        assert_eq!(path(&source, 0, 2).unwrap(), ["v0", "v2"]);
+        assert_eq!(source.plan(0, 2).unwrap()[0].patch.unwrap().link, "v0-v2");
+        // The jump doesn't prevent reaching the version it skips
+        assert_eq!(path(&source, 0, 1).unwrap(), ["v0", "v1"]);
+    }
+
+    #[test]
+    fn expensive_jump_is_ignored() {
+        let source = source(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    archive_size: 10
+    jumps:
+      - to: v2
+        link: v0-v2
+        archive_size: 25
+    determinants: []
+  - name: v1
+    update_link: v1-v2
+    archive_size: 10
+    determinants: []
+  - name: v2
+    determinants: []
+",
+        );
+        assert_eq!(path(&source, 0, 2).unwrap(), ["v0", "v1", "v2"]);
+    }
+
+    #[test]
+    fn jump_can_be_followed_by_updates() {
+        let source = source(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    archive_size: 10
+    jumps:
+      - to: v2
+        link: v0-v2
+        archive_size: 1
+    determinants: []
+  - name: v1
+    update_link: v1-v2
+    archive_size: 10
+    determinants: []
+  - name: v2
+    update_link: v2-v3
+    archive_size: 10
+    determinants: []
+  - name: v3
+    determinants: []
+",
+        );
+        assert_eq!(path(&source, 0, 3).unwrap(), ["v0", "v2", "v3"]);
+    }
+
+    #[test]
+    fn unknown_sizes_are_avoided() {
+        let source = source(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    archive_size: 100
+    jumps:
+      - to: v2
+        link: v0-v2
+    determinants: []
+  - name: v1
+    update_link: v1-v2
+    archive_size: 100
+    determinants: []
+  - name: v2
+    determinants: []
+",
+        );
+        assert_eq!(path(&source, 0, 2).unwrap(), ["v0", "v1", "v2"]);
+    }
+
+    #[test]
+    fn fewer_steps_break_ties() {
+        let source = source(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    archive_size: 10
+    jumps:
+      - to: v2
+        link: v0-v2
+        archive_size: 20
+    determinants: []
+  - name: v1
+    update_link: v1-v2
+    archive_size: 10
+    determinants: []
+  - name: v2
+    determinants: []
+",
+        );
+        assert_eq!(path(&source, 0, 2).unwrap(), ["v0", "v2"]);
+    }
+
+    #[test]
+    fn jumps_to_unknown_versions_are_ignored() {
+        let source = source(
+            "
+versions:
+  - name: v0
+    jumps:
+      - to: v9
+        link: v0-v9
+    determinants: []
+  - name: v1
+    determinants: []
+",
+        );
+        assert!(source.reachable_versions(0).is_empty());
+    }
+
+    #[test]
+    fn plan_to_checks_the_target() {
+        let source = source(CHAIN);
+        assert_eq!(source.plan_to(0, "v2").unwrap().len(), 2);
+        assert!(matches!(
+            source.plan_to(0, "v9"),
+            Err(PlanError::UnknownVersion(name)) if name == "v9"
+        ));
+        assert!(matches!(
+            source.plan_to(2, "v0"),
+            Err(PlanError::Unreachable { from, to }) if from == "v2" && to == "v0"
+        ));
+    }
+
+    #[test]
+    fn reachable_versions_are_later_ones() {
+        let source = source(CHAIN);
+        assert_eq!(source.reachable_versions(1), [2, 3]);
+        assert!(source.reachable_versions(3).is_empty());
+        assert!(source.reachable_versions(4).is_empty());
+    }
+
+    #[test]
+    fn plan_to_latest_reaches_the_last_version() {
+        let source = source(CHAIN);
+        let transitions = source.plan_to_latest(0);
+        assert_eq!(transitions.last().unwrap().new.name, "v3");
+        assert!(source.plan_to_latest(3).is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use super::{
+    channel::Channel,
+    source::{Jump, Source, Version},
+};
+
+///
+/// Whether something restricted to `target_os` and `target_arch` is meant for the platform `os`
+/// and `arch`. Missing targets match all platforms.
+///
+fn targets(target_os: &Option<String>, target_arch: &Option<String>, os: &str, arch: &str) -> bool {
+    target_os.as_ref().is_none_or(|x| x == os) && target_arch.as_ref().is_none_or(|x| x == arch)
+}
+
+impl Version {
+    ///
+    /// Whether this version is meant for the platform `os` and `arch`, named like
+    /// [`std::env::consts::OS`] and [`std::env::consts::ARCH`]. Versions without a target are
+    /// shared by all platforms.
+    ///
+    pub fn supports(&self, os: &str, arch: &str) -> bool {
+        targets(&self.target_os, &self.target_arch, os, arch)
+    }
+}
+
+impl Jump {
+    ///
+    /// Whether this archive is meant for the platform `os` and `arch`, see [`Version::supports`]
+    ///
+    pub fn supports(&self, os: &str, arch: &str) -> bool {
+        targets(&self.target_os, &self.target_arch, os, arch)
+    }
+}
+
+impl Channel {
+    ///
+    /// Whether this channel is meant for the platform `os` and `arch`, see [`Version::supports`]
+    ///
+    pub fn supports(&self, os: &str, arch: &str) -> bool {
+        targets(&self.target_os, &self.target_arch, os, arch)
+    }
+}
+
+impl Source {
+    ///
+    /// Keeps only the versions, jumps and channels meant for the platform `os` and `arch`, in
+    /// the default chain, the channels and the components
+    ///
+    pub fn for_platform(&self, os: &str, arch: &str) -> Self {
+        let filter = |versions: &[Version]| {
+            versions
+                .iter()
+                .filter(|x| x.supports(os, arch))
+                .map(|x| {
+                    let mut version = x.clone();
+                    version.jumps.retain(|jump| jump.supports(os, arch));
+                    version
+                })
+                .collect()
+        };
+        let mut source = self.clone();
+        source.versions = filter(&self.versions);
+        source.channels.retain(|x| x.supports(os, arch));
+        for channel in &mut source.channels {
+            channel.versions = filter(&channel.versions);
+        }
+        for component in &mut source.components {
+            component.versions = filter(&component.versions);
+        }
+        source
+    }
+
+    ///
+    /// Keeps only the versions meant for the platform the patcher runs on
+    ///
+    pub fn for_current_platform(&self) -> Self {
+        self.for_platform(std::env::consts::OS, std::env::consts::ARCH)
+    }
+
+    ///
+    /// Every combination of the platforms targeted by the versions, jumps and channels of this
+    /// source, along with any other platform, named `""`, which only gets what isn't restricted.
+    /// A source without targets has a single platform, matching everything.
+    ///
+    pub(crate) fn platforms(&self) -> Vec<(&str, &str)> {
+        let versions = || {
+            self.versions
+                .iter()
+                .chain(self.channels.iter().flat_map(|x| &x.versions))
+                .chain(self.components.iter().flat_map(|x| &x.versions))
+        };
+        let targets = || {
+            versions()
+                .map(|x| (&x.target_os, &x.target_arch))
+                .chain(
+                    versions()
+                        .flat_map(|x| &x.jumps)
+                        .map(|x| (&x.target_os, &x.target_arch)),
+                )
+                .chain(self.channels.iter().map(|x| (&x.target_os, &x.target_arch)))
+        };
+        let mut oses: Vec<&str> = targets().filter_map(|(os, _)| os.as_deref()).collect();
+        let mut arches: Vec<&str> = targets().filter_map(|(_, arch)| arch.as_deref()).collect();
+        for targets in [&mut oses, &mut arches] {
+            targets.push("");
+            targets.sort_unstable();
+            targets.dedup();
+        }
+        oses.iter()
+            .flat_map(|&os| arches.iter().map(move |&arch| (os, arch)))
+            .collect()
+    }
+}
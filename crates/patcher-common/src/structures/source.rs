@@ -1,81 +1,317 @@
-use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+use std::{collections::HashMap, fs::File, path::Path};
 
+use chrono::NaiveDate;
 use serde::Deserialize;
-use sha2::{Digest, Sha256};
 
-use crate::error::GlobalErrorType;
+use super::{channel::Channel, component::Component};
+use crate::{
+    error::GlobalErrorType,
+    hash::{HashAlgorithm, hash_file},
+    self_update::PatcherRelease,
+    state::InstalledState,
+};
 
-#[derive(Debug, Deserialize, Clone)]
+///
+/// A condition on a file of the install directory. Every condition set must hold: the file
+/// exists, unless `absent` is set, and has the given `size` and hash.
+///
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash, Default)]
 pub struct Determinants {
     pub file: String,
-    pub sha256: String,
+    pub sha256: Option<String>,
+    pub sha512: Option<String>,
+    pub blake3: Option<String>,
+    /// Fast hash, only meant to tell versions apart
+    pub xxh3: Option<String>,
+    /// Size in bytes of the file, checked before hashing it
+    pub size: Option<u64>,
+    /// Whether the file must not exist
+    #[serde(default)]
+    pub absent: bool,
+}
+
+impl Determinants {
+    ///
+    /// Every hash the file must have, along with its algorithm
+    ///
+    pub fn hashes(&self) -> impl Iterator<Item = (HashAlgorithm, &str)> {
+        [&self.sha256, &self.sha512, &self.blake3, &self.xxh3]
+            .into_iter()
+            .zip(HashAlgorithm::ALL)
+            .filter_map(|(hash, algorithm)| Some((algorithm, hash.as_deref()?)))
+    }
+
+    ///
+    /// The hash the file must have, along with its algorithm. Only one hash may be set, see
+    /// [`Source::validate`]
+    ///
+    pub fn hash(&self) -> Option<(HashAlgorithm, &str)> {
+        self.hashes().next()
+    }
+
+    pub fn set_hash(&mut self, algorithm: HashAlgorithm, hash: String) {
+        let field = match algorithm {
+            HashAlgorithm::Sha256 => &mut self.sha256,
+            HashAlgorithm::Sha512 => &mut self.sha512,
+            HashAlgorithm::Blake3 => &mut self.blake3,
+            HashAlgorithm::Xxh3 => &mut self.xxh3,
+        };
+        *field = Some(hash);
+    }
+
+    ///
+    /// Checks the conditions that don't require reading the file: whether it exists, and its size
+    ///
+    pub(crate) fn matches_metadata(&self, original: &Path) -> bool {
+        match std::fs::metadata(original.join(&self.file)) {
+            Ok(metadata) => !self.absent && self.size.is_none_or(|size| metadata.len() == size),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => self.absent,
+            Err(e) => {
+                log::trace!("error while reading metadata of `{}`: `{e}`", self.file);
+                false
+            }
+        }
+    }
+
+    ///
+    /// Checks every condition on the file, hashing it only if the other conditions hold
+    ///
+    pub fn matches(&self, original: &Path) -> std::io::Result<bool> {
+        if !self.matches_metadata(original) {
+            return Ok(false);
+        }
+        let Some((algorithm, hash)) = self.hash() else {
+            return Ok(true);
+        };
+        Ok(hash_file(File::open(original.join(&self.file))?, algorithm)? == hash)
+    }
+}
+
+///
+/// An archive transforming a version into another
+///
+#[derive(Debug, Deserialize, Clone)]
+pub struct PatchLink {
+    pub link: String,
+    /// Size in bytes of the archive behind `link`
+    pub archive_size: Option<u64>,
+    /// Size in bytes of the files obtained once the archive behind `link` is applied
+    pub unpacked_size: Option<u64>,
+}
+
+impl PatchLink {
+    pub fn as_ref(&self) -> PatchLinkRef<'_> {
+        PatchLinkRef {
+            link: &self.link,
+            archive_size: self.archive_size,
+            unpacked_size: self.unpacked_size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PatchLinkRef<'a> {
+    pub link: &'a str,
+    pub archive_size: Option<u64>,
+    pub unpacked_size: Option<u64>,
+}
+
+impl PatchLinkRef<'_> {
+    pub fn to_owned(&self) -> PatchLink {
+        PatchLink {
+            link: self.link.to_owned(),
+            archive_size: self.archive_size,
+            unpacked_size: self.unpacked_size,
+        }
+    }
+}
+
+///
+/// An additional archive going directly from a version to another one, usually skipping
+/// intermediate versions
+///
+#[derive(Debug, Deserialize, Clone)]
+pub struct Jump {
+    pub to: String,
+    #[serde(flatten)]
+    pub patch: PatchLink,
+    /// The operating system this archive is meant for, all of them when `None`
+    pub target_os: Option<String>,
+    /// The architecture this archive is meant for, all of them when `None`
+    pub target_arch: Option<String>,
+}
+
+///
+/// How much users are urged to install a version
+///
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Importance {
+    Optional,
+    Recommended,
+    Critical,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Version {
     pub name: String,
+    pub release_date: Option<NaiveDate>,
+    /// What changed since the previous version, in markdown
+    pub changelog: Option<String>,
+    pub importance: Option<Importance>,
     pub update_link: Option<String>,
+    /// Size in bytes of the archive behind `update_link`
+    pub archive_size: Option<u64>,
+    /// Size in bytes of the files obtained once the archive behind `update_link` is applied
+    pub unpacked_size: Option<u64>,
+    /// Archive going back from this version to the previous one
+    pub downgrade: Option<PatchLink>,
+    /// Archives going directly from this version to later ones
+    #[serde(default)]
+    pub jumps: Vec<Jump>,
+    /// Archive containing every file of this version, installed when no version is detected
+    pub full_install: Option<PatchLink>,
+    /// Link to the [`crate::manifest::Manifest`] of this version
+    pub manifest: Option<String>,
     pub determinants: Vec<Determinants>,
+    /// The operating system this version is meant for, like `windows` or `linux`, all of them
+    /// when `None`
+    pub target_os: Option<String>,
+    /// The architecture this version is meant for, like `x86_64` or `aarch64`, all of them when
+    /// `None`
+    pub target_arch: Option<String>,
+    /// The component this version belongs to, set by [`Source::component`]
+    #[serde(skip)]
+    pub component: Option<String>,
+}
+
+impl Version {
+    pub fn formatted_release_date(&self) -> Option<String> {
+        Some(self.release_date?.format("%d/%m/%Y").to_string())
+    }
+
+    ///
+    /// The archive going from this version to the next one
+    ///
+    pub fn update_patch(&self) -> Option<PatchLinkRef<'_>> {
+        Some(PatchLinkRef {
+            link: self.update_link.as_ref()?,
+            archive_size: self.archive_size,
+            unpacked_size: self.unpacked_size,
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Source {
     pub versions: Vec<Version>,
+    /// Alternative version chains, see [`Source::channel`]
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    /// Optional parts of the install, see [`Source::component`]
+    #[serde(default)]
+    pub components: Vec<Component>,
+    /// The latest release of the patcher itself
+    pub patcher: Option<PatcherRelease>,
 }
 
 pub struct VersionTransition {
     pub old: Version,
     pub new: Version,
+    pub patch: Option<PatchLink>,
 }
 
 impl VersionTransition {
     pub fn as_ref(&self) -> VersionTransitionRef<'_> {
-        VersionTransitionRef { old: &self.old, new: &self.new }
+        VersionTransitionRef {
+            old: &self.old,
+            new: &self.new,
+            patch: self.patch.as_ref().map(PatchLink::as_ref),
+        }
     }
 }
 
 pub struct VersionTransitionRef<'a> {
     pub old: &'a Version,
-    pub new: &'a Version
+    pub new: &'a Version,
+    /// The archive to apply on `old` to get `new`
+    pub patch: Option<PatchLinkRef<'a>>,
 }
 
 impl VersionTransitionRef<'_> {
     pub fn to_owned(&self) -> VersionTransition {
-        VersionTransition { old: self.old.clone(), new: self.new.clone() }
+        VersionTransition {
+            old: self.old.clone(),
+            new: self.new.clone(),
+            patch: self.patch.as_ref().map(PatchLinkRef::to_owned),
+        }
     }
 }
 
 impl Source {
+    ///
+    /// Uses the state file written after the last update as a first guess, only confirmed by
+    /// checking the size and modification date of the determinants
+    ///
+    fn get_version_from_state(&self, path: &Path) -> Option<usize> {
+        let state = InstalledState::read(path, self.component_name())?;
+        let found = self
+            .versions
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, version)| state.matches(path, version));
+        match found {
+            Some((i, version)) => {
+                log::trace!("version `{}` confirmed by the state file", version.name);
+                Some(i)
+            }
+            None => {
+                log::trace!("state file doesn't match the install, falling back to hashing");
+                None
+            }
+        }
+    }
+
     pub fn get_current_version(&self, path: &Path) -> Result<Option<usize>, std::io::Error> {
-        let mut already_calculated: HashMap<&str, [u8; 64]> = HashMap::new();
+        if let Some(i) = self.get_version_from_state(path) {
+            return Ok(Some(i));
+        }
+
+        let mut already_calculated: HashMap<(&str, HashAlgorithm), String> = HashMap::new();
         'version: for (i, version) in self.versions.iter().enumerate().rev() {
             log::trace!("checking version `{}`", version.name);
+            // Cheap checks of all the determinants come first, hashing is only done if they pass
+            if let Some(determinant) = version
+                .determinants
+                .iter()
+                .find(|determinant| !determinant.matches_metadata(path))
+            {
+                log::trace!("metadata mismatch for file `{}`", determinant.file);
+                continue 'version;
+            }
             for determinant in &version.determinants {
-                let hash = if let Some(x) = already_calculated.get(determinant.file.as_str()) {
+                let Some((algorithm, expected)) = determinant.hash() else {
+                    continue;
+                };
+                let key = (determinant.file.as_str(), algorithm);
+                let hash = if let Some(x) = already_calculated.get(&key) {
                     x
                 } else {
-                    let mut hasher = Sha256::new();
-                    let path = path.join(&determinant.file);
-                    let file = match File::open(&path) {
+                    let file = match File::open(path.join(&determinant.file)) {
                         Ok(f) => f,
                         Err(e) => {
                             log::trace!("error while opening file `{}`: `{e}`", determinant.file);
                             continue 'version;
                         }
                     };
-                    std::io::copy(&mut BufReader::new(file), &mut hasher)?;
-                    let mut buffer = [0; 64];
-                    match base16ct::lower::encode(&hasher.finalize(), &mut buffer) {
-                        Ok(_) => (),
-                        Err(e) => unreachable!("64-byte should always be enough: {e}"),
-                    }
                     already_calculated
-                        .entry(&determinant.file)
-                        .or_insert(buffer)
+                        .entry(key)
+                        .or_insert(hash_file(file, algorithm)?)
                 };
 
-                if hash != determinant.sha256.as_bytes() {
-                    log::trace!("sha256 mismatch for file `{}`", determinant.file);
+                if hash != expected {
+                    log::trace!("{algorithm} mismatch for file `{}`", determinant.file);
                     continue 'version;
                 }
             }
@@ -86,58 +322,88 @@ impl Source {
     }
 
     ///
-    /// Gets a slice of all versions from the current one to the last having an update link
-    /// 
-    pub fn get_versions_to_install(&self, current: usize) -> &[Version] {
-        let versions_to_install = self
-            .versions
-            .split_at_checked(current)
-            .unwrap_or_default()
-            .1;
-
-        versions_to_install
-            .iter()
-            .position(|x| x.update_link.is_none())
-            .map_or(versions_to_install, |pos| {
-                versions_to_install.split_at(pos).0
-            })
+    /// Gets the most recent version that can be installed from scratch
+    ///
+    pub fn latest_full_install(&self) -> Option<usize> {
+        self.versions.iter().rposition(|x| x.full_install.is_some())
     }
 
     ///
-    /// Gets all versions transition between the current one and the last
-    /// 
-    pub fn get_transitions(&self, current: usize) -> impl ExactSizeIterator<Item = VersionTransitionRef<'_>> {
-        let versions_to_install = self
-            .versions
-            .split_at_checked(current)
-            .unwrap_or_default()
-            .1;
-
-        let last_update = versions_to_install
-            .iter()
-            .position(|x| x.update_link.is_none());
-
-        let trimmed_slice = match last_update {
-            Some(x) => {
-                if x + 1 >= versions_to_install.len() {
-                    versions_to_install
+    /// Fetches and validates the source at `url`, keeping only the versions meant for the
+    /// platform the patcher runs on
+    ///
+    pub fn from_url(url: &str) -> Result<Self, GlobalErrorType> {
+        match minreq::get(url).send() {
+            Ok(x) => {
+                let source: Self = serde_yaml::from_slice(x.as_bytes())?;
+                let errors = source.validate();
+                if errors.is_empty() {
+                    Ok(source.for_current_platform())
                 } else {
-                    &versions_to_install[..x + 1]
+                    Err(GlobalErrorType::InvalidSource(errors))
                 }
             }
-            None => versions_to_install
-        };
-        
-        trimmed_slice.windows(2)
-            .map(|slice| {
-                VersionTransitionRef { old: &slice[0], new: &slice[1] }
-            })
+            Err(e) => Err(e)?,
+        }
     }
+}
 
-    pub fn from_url(url: &str) -> Result<Self, GlobalErrorType> {
-        match minreq::get(url).send() {
-            Ok(x) => Ok(serde_yaml::from_slice(x.as_bytes())?),
-            Err(e) => Err(e)?
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA-256 of `hello`
+    const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn determinant(yaml: &str) -> Determinants {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn absent_files() {
+        let original = tempfile::tempdir().unwrap();
+        let absent = determinant("{file: a, absent: true}");
+        assert!(absent.matches_metadata(original.path()));
+        assert!(absent.matches(original.path()).unwrap());
+
+        std::fs::write(original.path().join("a"), "hello").unwrap();
+        assert!(!absent.matches_metadata(original.path()));
+        assert!(!absent.matches(original.path()).unwrap());
+    }
+
+    #[test]
+    fn missing_files() {
+        let original = tempfile::tempdir().unwrap();
+        let present = determinant("{file: a}");
+        assert!(!present.matches_metadata(original.path()));
+        assert!(!present.matches(original.path()).unwrap());
+    }
+
+    #[test]
+    fn size_is_checked_before_hashing() {
+        let original = tempfile::tempdir().unwrap();
+        std::fs::write(original.path().join("a"), "hello").unwrap();
+
+        let size_only = determinant("{file: a, size: 5}");
+        assert!(size_only.matches_metadata(original.path()));
+        assert!(size_only.matches(original.path()).unwrap());
+
+        // The hash would match, but the size doesn't
+        let wrong_size = determinant(&format!("{{file: a, size: 4, sha256: {HELLO}}}"));
+        assert!(!wrong_size.matches_metadata(original.path()));
+        assert!(!wrong_size.matches(original.path()).unwrap());
+    }
+
+    #[test]
+    fn hash_is_checked_last() {
+        let original = tempfile::tempdir().unwrap();
+        std::fs::write(original.path().join("a"), "hello").unwrap();
+
+        let right = determinant(&format!("{{file: a, size: 5, sha256: {HELLO}}}"));
+        assert!(right.matches(original.path()).unwrap());
+
+        let wrong = determinant(&format!("{{file: a, size: 5, sha256: {}}}", "0".repeat(64)));
+        assert!(wrong.matches_metadata(original.path()));
+        assert!(!wrong.matches(original.path()).unwrap());
     }
 }
@@ -0,0 +1,582 @@
+use std::collections::HashSet;
+
+use super::{
+    channel::DEFAULT_CHANNEL,
+    source::{Source, Version},
+};
+use crate::{error::ValidationError, hash::HashAlgorithm};
+
+impl Source {
+    ///
+    /// Checks the structure of the source, and of the version chain of each of its channels and
+    /// components, as seen from each platform it targets.
+    /// Every problem found is reported, not only the first one.
+    ///
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let platforms = self.platforms();
+        for &(os, arch) in &platforms {
+            for error in self.for_platform(os, arch).validate_platform() {
+                if !errors.contains(&error) {
+                    errors.push(error);
+                }
+            }
+        }
+        for versions in std::iter::once(&self.versions)
+            .chain(self.channels.iter().map(|x| &x.versions))
+            .chain(self.components.iter().map(|x| &x.versions))
+        {
+            for error in validate_shared_versions(versions, &platforms) {
+                if !errors.contains(&error) {
+                    errors.push(error);
+                }
+            }
+        }
+        for download in self.patcher.iter().flat_map(|x| &x.downloads) {
+            if !HashAlgorithm::Sha256.is_valid(&download.sha256) {
+                errors.push(ValidationError::InvalidPatcherChecksum(
+                    download.link.clone(),
+                ));
+            }
+        }
+        errors
+    }
+
+    fn validate_platform(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let mut channels = HashSet::from([DEFAULT_CHANNEL]);
+        for channel in &self.channels {
+            if !channels.insert(&channel.name) {
+                errors.push(ValidationError::DuplicateChannel(channel.name.clone()));
+            }
+        }
+        let mut components = HashSet::new();
+        for component in &self.components {
+            if !components.insert(&component.name) {
+                errors.push(ValidationError::DuplicateComponent(component.name.clone()));
+            }
+        }
+        for name in self.channel_names() {
+            match self.channel(Some(name)) {
+                Ok(chain) => {
+                    for error in chain.validate_chain() {
+                        if !errors.contains(&error) {
+                            errors.push(error);
+                        }
+                    }
+                }
+                Err(e) => errors.push(e.into()),
+            }
+        }
+        for component in &self.components {
+            let Ok(chain) = self.component(&component.name) else {
+                continue;
+            };
+            for error in chain.validate_chain() {
+                if !errors.contains(&error) {
+                    errors.push(error);
+                }
+            }
+        }
+        errors
+    }
+
+    fn validate_chain(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+
+        let mut names = HashSet::new();
+        for version in &self.versions {
+            if !names.insert(&version.name) {
+                errors.push(ValidationError::DuplicateName(version.name.clone()));
+            }
+            if version.determinants.is_empty() {
+                errors.push(ValidationError::NoDeterminants(version.name.clone()));
+            }
+            for determinant in &version.determinants {
+                if determinant.absent
+                    && (determinant.hash().is_some() || determinant.size.is_some())
+                {
+                    errors.push(ValidationError::ContradictoryDeterminant {
+                        version: version.name.clone(),
+                        file: determinant.file.clone(),
+                    });
+                }
+                if determinant.hashes().nth(1).is_some() {
+                    errors.push(ValidationError::SeveralHashes {
+                        version: version.name.clone(),
+                        file: determinant.file.clone(),
+                    });
+                }
+                for (algorithm, hash) in determinant.hashes() {
+                    if !algorithm.is_valid(hash) {
+                        errors.push(ValidationError::InvalidHash {
+                            version: version.name.clone(),
+                            file: determinant.file.clone(),
+                            algorithm,
+                        });
+                    }
+                }
+            }
+            for jump in &version.jumps {
+                if !self.versions.iter().any(|x| x.name == jump.to) {
+                    errors.push(ValidationError::UnknownJumpTarget {
+                        version: version.name.clone(),
+                        to: jump.to.clone(),
+                    });
+                }
+            }
+        }
+
+        let reachable = self.reachable_versions(0);
+        for (i, version) in self.versions.iter().enumerate().skip(1) {
+            if !reachable.contains(&i) {
+                errors.push(ValidationError::Unreachable(version.name.clone()));
+            }
+        }
+
+        for (i, version) in self.versions.iter().enumerate() {
+            if version.determinants.is_empty() {
+                continue;
+            }
+            let determinants: HashSet<_> = version.determinants.iter().collect();
+            for other in &self.versions[i + 1..] {
+                if other.determinants.len() == determinants.len()
+                    && other.determinants.iter().all(|x| determinants.contains(x))
+                {
+                    errors.push(ValidationError::Ambiguous {
+                        first: version.name.clone(),
+                        second: other.name.clone(),
+                    });
+                }
+            }
+        }
+        errors
+    }
+}
+
+///
+/// Checks that the `update_link` of each version shared by several platforms leads to the same
+/// version on all of them, as a single archive cannot produce different versions. A version
+/// followed by different versions must have a jump to the next one of each platform instead.
+///
+fn validate_shared_versions(
+    versions: &[Version],
+    platforms: &[(&str, &str)],
+) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    for (i, version) in versions.iter().enumerate() {
+        if version.update_link.is_none() {
+            continue;
+        }
+        let next: Vec<_> = platforms
+            .iter()
+            .filter(|(os, arch)| version.supports(os, arch))
+            .filter_map(|&(os, arch)| {
+                let next = versions[i + 1..].iter().find(|x| x.supports(os, arch))?;
+                Some((os, arch, next))
+            })
+            .collect();
+        let shared = next.iter().all(|(_, _, x)| std::ptr::eq(*x, next[0].2));
+        let jumps = next.iter().all(|(os, arch, next)| {
+            version
+                .jumps
+                .iter()
+                .any(|jump| jump.supports(os, arch) && jump.to == next.name)
+        });
+        if !shared && !jumps {
+            errors.push(ValidationError::SharedUpdateLink(version.name.clone()));
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ChannelError;
+
+    const SHA256: &str = "f09eb9f4aa1139cc9e04c8193f41adf2be4b31f3c779a85d217b2725732650e7";
+
+    fn errors(yaml: &str) -> Vec<ValidationError> {
+        serde_yaml::from_str::<Source>(yaml).unwrap().validate()
+    }
+
+    #[test]
+    fn valid_source() {
+        let errors = errors(&format!(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    jumps:
+      - to: v2
+        link: v0-v2
+    determinants:
+      - file: a
+        sha256: {SHA256}
+  - name: v1
+    update_link: v1-v2
+    determinants:
+      - file: a
+        size: 12
+  - name: v2
+    determinants:
+      - file: a
+        absent: true
+"
+        ));
+        assert_eq!(errors, []);
+    }
+
+    #[test]
+    fn duplicate_version_names() {
+        let errors = errors(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    determinants:
+      - file: a
+  - name: v0
+    determinants:
+      - file: b
+",
+        );
+        assert_eq!(errors, [ValidationError::DuplicateName("v0".to_string())]);
+    }
+
+    #[test]
+    fn same_name_on_different_platforms() {
+        let errors = errors(
+            "
+versions:
+  - name: v0
+    jumps:
+      - to: v1
+        link: v0-v1-windows
+        target_os: windows
+      - to: v1
+        link: v0-v1-linux
+        target_os: linux
+    determinants:
+      - file: a
+  - name: v1
+    target_os: windows
+    determinants:
+      - file: game.exe
+  - name: v1
+    target_os: linux
+    determinants:
+      - file: game
+",
+        );
+        assert_eq!(errors, []);
+    }
+
+    #[test]
+    fn shared_update_link_to_different_versions() {
+        let errors = errors(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    determinants:
+      - file: a
+  - name: v1
+    target_os: windows
+    determinants:
+      - file: game.exe
+  - name: v1
+    target_os: linux
+    determinants:
+      - file: game
+",
+        );
+        assert_eq!(
+            errors,
+            [ValidationError::SharedUpdateLink("v0".to_string())]
+        );
+    }
+
+    #[test]
+    fn shared_update_link_with_jumps_for_each_platform() {
+        let errors = errors(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    jumps:
+      - to: v1
+        link: v0-v1-windows
+        target_os: windows
+      - to: v1
+        link: v0-v1-linux
+        target_os: linux
+    determinants:
+      - file: a
+  - name: v1
+    target_os: windows
+    determinants:
+      - file: game.exe
+  - name: v1
+    target_os: linux
+    determinants:
+      - file: game
+",
+        );
+        assert_eq!(errors, []);
+    }
+
+    #[test]
+    fn other_platforms_are_validated() {
+        let errors = errors(
+            "
+versions:
+  - name: v0
+    jumps:
+      - to: v1
+        link: v0-v1-windows
+        target_os: windows
+    determinants:
+      - file: a
+  - name: v1
+    determinants:
+      - file: b
+",
+        );
+        assert_eq!(errors, [ValidationError::Unreachable("v1".to_string())]);
+    }
+
+    #[test]
+    fn jumps_and_channels_on_different_platforms() {
+        let source: Source = serde_yaml::from_str(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    jumps:
+      - to: v2
+        link: v0-v2-windows
+        target_os: windows
+      - to: v2
+        link: v0-v2-linux
+        target_os: linux
+    determinants:
+      - file: a
+  - name: v1
+    update_link: v1-v2
+    determinants:
+      - file: b
+  - name: v2
+    determinants:
+      - file: c
+channels:
+  - name: beta
+    branch: v0
+    link: v0-beta-windows
+    target_os: windows
+    versions:
+      - name: beta
+        determinants:
+          - file: d
+  - name: beta
+    branch: v0
+    link: v0-beta-linux
+    target_os: linux
+    versions:
+      - name: beta
+        determinants:
+          - file: d
+",
+        )
+        .unwrap();
+        assert_eq!(source.validate(), []);
+
+        let linux = source.for_platform("linux", "x86_64");
+        let jumps: Vec<_> = linux.versions[0]
+            .jumps
+            .iter()
+            .map(|x| &x.patch.link)
+            .collect();
+        assert_eq!(jumps, ["v0-v2-linux"]);
+        let beta = linux.channel(Some("beta")).unwrap();
+        assert_eq!(
+            beta.versions[0].update_link.as_deref(),
+            Some("v0-beta-linux")
+        );
+    }
+
+    #[test]
+    fn invalid_determinants() {
+        let errors = errors(&format!(
+            "
+versions:
+  - name: v0
+    determinants:
+      - file: a
+        sha256: not-a-hash
+      - file: b
+        absent: true
+        size: 3
+      - file: c
+        sha256: {SHA256}
+        xxh3: 0123456789abcdef
+  - name: v1
+    determinants: []
+"
+        ));
+        let version = || "v0".to_string();
+        assert!(errors.contains(&ValidationError::InvalidHash {
+            version: version(),
+            file: "a".to_string(),
+            algorithm: HashAlgorithm::Sha256,
+        }));
+        assert!(errors.contains(&ValidationError::ContradictoryDeterminant {
+            version: version(),
+            file: "b".to_string(),
+        }));
+        assert!(errors.contains(&ValidationError::SeveralHashes {
+            version: version(),
+            file: "c".to_string(),
+        }));
+        assert!(errors.contains(&ValidationError::NoDeterminants("v1".to_string())));
+    }
+
+    #[test]
+    fn unreachable_versions_and_unknown_jumps() {
+        let errors = errors(
+            "
+versions:
+  - name: v0
+    jumps:
+      - to: v9
+        link: v0-v9
+    determinants:
+      - file: a
+  - name: v1
+    determinants:
+      - file: b
+",
+        );
+        assert_eq!(
+            errors,
+            [
+                ValidationError::UnknownJumpTarget {
+                    version: "v0".to_string(),
+                    to: "v9".to_string(),
+                },
+                ValidationError::Unreachable("v1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ambiguous_versions() {
+        let errors = errors(
+            "
+versions:
+  - name: v0
+    update_link: v0-v1
+    determinants:
+      - file: a
+      - file: b
+  - name: v1
+    determinants:
+      - file: b
+      - file: a
+",
+        );
+        assert_eq!(
+            errors,
+            [ValidationError::Ambiguous {
+                first: "v0".to_string(),
+                second: "v1".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn channels_and_components() {
+        let errors = errors(
+            "
+versions:
+  - name: v0
+    determinants:
+      - file: a
+channels:
+  - name: beta
+    branch: v0
+    link: v0-beta
+    versions:
+      - name: beta.1
+        determinants:
+          - file: beta
+  - name: beta
+    branch: v0
+    link: v0-beta
+    versions: []
+  - name: stable
+    branch: v0
+    link: v0-beta
+    versions: []
+  - name: nightly
+    branch: v9
+    link: v9-nightly
+    versions: []
+components:
+  - name: voices
+    versions:
+      - name: v1
+        determinants:
+          - file: voices/v1
+      - name: v2
+        determinants:
+          - file: voices/v2
+  - name: voices
+    versions: []
+",
+        );
+        assert_eq!(
+            errors,
+            [
+                ValidationError::DuplicateChannel("beta".to_string()),
+                ValidationError::DuplicateChannel("stable".to_string()),
+                ValidationError::DuplicateComponent("voices".to_string()),
+                ValidationError::Channel(ChannelError::BranchNotFound {
+                    channel: "nightly".to_string(),
+                    branch: "v9".to_string(),
+                }),
+                ValidationError::Unreachable("v2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_patcher_checksum() {
+        let errors = errors(&format!(
+            "
+versions:
+  - name: v0
+    determinants:
+      - file: a
+patcher:
+  version: 1.0.0
+  downloads:
+    - executable: patcher-gui
+      target_os: windows
+      link: valid
+      sha256: {SHA256}
+    - executable: patcher-gui
+      target_os: linux
+      link: invalid
+      sha256: 1234
+"
+        ));
+        assert_eq!(
+            errors,
+            [ValidationError::InvalidPatcherChecksum(
+                "invalid".to_string()
+            )]
+        );
+    }
+}
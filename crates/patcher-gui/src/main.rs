@@ -7,13 +7,11 @@
     clippy::nursery
 )]
 
-use std::process::ExitCode;
+use crate::ui::{AppScreen, patcher::Patcher};
 use eframe::egui;
 use log4rs::config::Deserializers;
-use crate::ui::{AppScreen, patcher::Patcher};
 use patcher_common::structures::{config::PatcherConfig, source::Source};
-
-
+use std::process::ExitCode;
 
 mod ui;
 
@@ -31,6 +29,7 @@ fn main() -> ExitCode {
     let _ = log4rs::init_file("log4rs.yaml", Deserializers::default());
 
     let mut config = get_config();
+    patcher_common::self_update::remove_previous_executable();
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -65,7 +64,7 @@ impl MyApp {
             Ok(source) => {
                 log::debug!("source fetched successfully");
                 Self {
-                    app_screen: AppScreen::Patcher(Patcher::new(config, source)),
+                    app_screen: AppScreen::Patcher(Box::new(Patcher::new(config, source))),
                 }
             }
             Err(e) => {
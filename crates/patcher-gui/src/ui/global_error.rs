@@ -16,6 +16,13 @@ impl SourceError {
                 ui.colored_label(Color32::RED, "Le format de la source est invalide.");
                 e.to_string()
             }
+            GlobalErrorType::InvalidSource(errors) => {
+                ui.colored_label(Color32::RED, "La source contient des erreurs.");
+                for error in errors {
+                    ui.code(RichText::new(error.to_string()).color(Color32::RED));
+                }
+                return;
+            }
         };
         ui.code(RichText::new(e).color(Color32::RED));
     }
@@ -4,7 +4,7 @@ pub mod global_error;
 pub mod patcher;
 
 pub enum AppScreen {
-    Patcher(patcher::Patcher),
+    Patcher(Box<patcher::Patcher>),
     SourceError(global_error::SourceError),
 }
 
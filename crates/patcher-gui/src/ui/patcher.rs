@@ -1,12 +1,28 @@
 use std::{
     io,
-    path::Path,
+    path::{Path, PathBuf},
     sync::mpsc::{self, Receiver},
 };
 
-use patcher_common::{download::ProgressReporter, error::DownloadAndPatchError, structures::{config::PatcherConfig, source::{Source, VersionTransition}}};
-use eframe::egui::{Color32, ProgressBar, RichText, Ui};
+use eframe::egui::{
+    Color32, ComboBox, Context, ProgressBar, RichText, ScrollArea, Ui, ViewportCommand,
+};
 use patcher_common::error::GetVersionError;
+use patcher_common::{
+    backup::Backup,
+    download::{PatchOptions, ProgressReporter},
+    error::{DownloadAndPatchError, RollbackError, SelfUpdateError},
+    history::{HistoryEntry, Outcome},
+    manifest::{ConflictPolicy, Conflicts},
+    self_update::PatcherDownload,
+    settings::Settings,
+    structures::{
+        channel::DEFAULT_CHANNEL,
+        config::PatcherConfig,
+        source::{Importance, Source, VersionTransition, VersionTransitionRef},
+    },
+};
+use thl_patcher::CancellationToken;
 
 #[derive(Debug)]
 enum Version {
@@ -19,44 +35,100 @@ enum Version {
 #[derive(Debug)]
 enum Progress {
     NotUpdating,
-    Updating {
-        done: u32,
-        out_of: u32,
-    },
+    Updating { done: u32, out_of: u32 },
     Updated,
+    Restored,
+    Cancelled,
 }
 
 pub struct Patcher {
+    /// The source as fetched, with all its channels
+    full_source: Source,
+    /// The version chain of the followed channel
     source: Source,
+    channel: String,
+    default_channel: Option<String>,
     progress: Progress,
     version: Version,
+    /// The version the update will go to, the latest reachable one when `None`
+    target: Option<usize>,
     selected_path: Option<String>,
     receiver: Option<Receiver<NewAction>>,
+    cancellation: Option<CancellationToken>,
     sub_progressbar_text: Option<String>,
     download_error: Option<DownloadAndPatchError>,
+    /// The optional components of the source, detected in the selected directory
+    components: Vec<InstalledComponent>,
+    /// The newer release of the patcher, with the executable replacing this one
+    new_release: Option<(String, PatcherDownload)>,
+    /// The new executable, once the patcher was replaced
+    relaunch: Option<PathBuf>,
+    self_update_error: Option<SelfUpdateError>,
+    /// Files modified by the user, waiting for a [`ConflictPolicy`] before applying the
+    /// transitions
+    conflicts: Option<(Conflicts, Vec<VersionTransition>)>,
+    history: Option<Result<Vec<HistoryEntry>, String>>,
+    keep_backups: bool,
+    last_backup: Option<Backup>,
+    rollback_error: Option<RollbackError>,
+}
+
+struct InstalledComponent {
+    name: String,
+    description: Option<String>,
+    /// The version chain of the component
+    chain: Source,
+    /// The version installed, `None` when the component isn't installed
+    version: Option<usize>,
+    /// The backup of the last update of the component, if it was kept
+    last_backup: Option<Backup>,
 }
 
+const EXECUTABLE: &str = "patcher-gui";
+
 enum NewAction {
     Downloading(String),
     Patching(String),
     FinishSingleVersion,
     Finish,
+    Restored,
+    /// The files modified by the user were looked for, before applying `versions_to_install`
+    ConflictsFound {
+        conflicts: Option<Box<Conflicts>>,
+        versions_to_install: Vec<VersionTransition>,
+    },
+    RolledBack,
+    RollbackError(RollbackError),
+    PatcherUpdated(PathBuf),
+    SelfUpdateError(SelfUpdateError),
     DownloadAndPatchError(DownloadAndPatchError),
 }
 
 pub struct ProgressTracker {
     ctx: eframe::egui::Context,
-    tx: std::sync::mpsc::Sender<NewAction>
+    tx: std::sync::mpsc::Sender<NewAction>,
 }
 
 impl ProgressReporter for ProgressTracker {
-    fn on_start_new_version(&mut self, transition: &patcher_common::structures::source::VersionTransitionRef) {
-        let _ = self.tx.send(NewAction::Downloading(transition.new.name.clone()));
+    fn on_start_new_version(
+        &mut self,
+        transition: &patcher_common::structures::source::VersionTransitionRef,
+    ) {
+        let _ = self
+            .tx
+            .send(NewAction::Downloading(transition.new.name.clone()));
+        self.ctx.request_repaint();
+    }
+
+    fn on_start_full_install(&mut self, version: &patcher_common::structures::source::Version) {
+        let _ = self.tx.send(NewAction::Downloading(version.name.clone()));
         self.ctx.request_repaint();
     }
 
     fn on_patching_file(&mut self, path: &Path) {
-        let _ = self.tx.send(NewAction::Patching(path.display().to_string()));
+        let _ = self
+            .tx
+            .send(NewAction::Patching(path.display().to_string()));
         self.ctx.request_repaint();
     }
 
@@ -90,34 +162,199 @@ impl Patcher {
             Err(GetVersionError::MissingPath) => Version::NotFetched,
             Err(GetVersionError::Io(err)) => Version::IoError(err),
         };
+        self.target = None;
+        self.refresh_components();
+        self.last_backup = self.selected_path.as_ref().and_then(|path| {
+            Backup::last(Path::new(path), None)
+                .inspect_err(|e| log::error!("error while reading backups: {e}"))
+                .ok()?
+        });
+    }
+
+    ///
+    /// Offers to replace the patcher with the newer release declared in the source, then
+    /// relaunches it
+    ///
+    fn patcher_update_panel(&mut self, ui: &mut Ui) {
+        if let Some(executable) = self.relaunch.take() {
+            match patcher_common::self_update::relaunch(&executable) {
+                Ok(()) => ui.ctx().send_viewport_cmd(ViewportCommand::Close),
+                Err(e) => {
+                    log::error!("couldn't relaunch the patcher: {e}");
+                    self.self_update_error = Some(e.into());
+                }
+            }
+            self.new_release = None;
+        }
+        if let Some((version, download)) = &self.new_release {
+            ui.colored_label(
+                Color32::YELLOW,
+                format!("Une nouvelle version du patcheur ({version}) est disponible."),
+            );
+            if self.receiver.is_none() && ui.button("Mettre à jour le patcheur").clicked() {
+                let download = download.clone();
+                let (tx, rx) = mpsc::channel();
+                let ctx = ui.ctx().clone();
+                self.receiver = Some(rx);
+                self.self_update_error = None;
+                std::thread::spawn(move || {
+                    let action = match patcher_common::self_update::self_update(
+                        &download,
+                        &CancellationToken::new(),
+                    ) {
+                        Ok(executable) => NewAction::PatcherUpdated(executable),
+                        Err(e) => {
+                            log::error!("patcher update failed: {e}");
+                            NewAction::SelfUpdateError(e)
+                        }
+                    };
+                    let _ = tx.send(action);
+                    ctx.request_repaint();
+                });
+            }
+        }
+        if let Some(ref error) = self.self_update_error {
+            ui.colored_label(Color32::RED, "La mise à jour du patcheur a échoué.");
+            ui.code(RichText::new(error.to_string()).color(Color32::RED));
+        }
+    }
+
+    ///
+    /// Detects the version installed of each optional component
+    ///
+    fn refresh_components(&mut self) {
+        let Some(path) = &self.selected_path else {
+            self.components = Vec::new();
+            return;
+        };
+        self.components = self
+            .source
+            .components
+            .iter()
+            .filter_map(|component| {
+                let chain = self.source.component(&component.name).ok()?;
+                let version = chain
+                    .get_current_version(Path::new(path))
+                    .inspect_err(|e| log::error!("error while detecting {}: {e}", component.name))
+                    .ok()
+                    .flatten();
+                let last_backup = Backup::last(Path::new(path), Some(&component.name))
+                    .inspect_err(|e| log::error!("error while reading backups: {e}"))
+                    .ok()
+                    .flatten();
+                Some(InstalledComponent {
+                    name: component.name.clone(),
+                    description: component.description.clone(),
+                    chain,
+                    version,
+                    last_backup,
+                })
+            })
+            .collect();
+    }
+
+    ///
+    /// Follows the channel chosen for the selected directory, or the default one of the config
+    ///
+    fn refresh_channel(&mut self) {
+        let channel = self
+            .selected_path
+            .as_ref()
+            .and_then(|path| Settings::read(Path::new(path)).channel)
+            .or_else(|| self.default_channel.clone());
+        self.source = match self.full_source.channel(channel.as_deref()) {
+            Ok(source) => {
+                self.channel = channel.unwrap_or_else(|| DEFAULT_CHANNEL.to_string());
+                source
+            }
+            Err(e) => {
+                log::error!("cannot follow channel: {e}");
+                self.channel = DEFAULT_CHANNEL.to_string();
+                self.full_source.clone()
+            }
+        };
+    }
+
+    fn channel_selector(&mut self, ui: &mut Ui) {
+        let Some(ref path) = self.selected_path else {
+            return;
+        };
+        if self.full_source.channels.is_empty() || self.receiver.is_some() {
+            return;
+        }
+        let mut channel = self.channel.clone();
+        ui.horizontal(|ui| {
+            ui.label("Canal :");
+            ComboBox::from_id_salt("channel")
+                .selected_text(&channel)
+                .show_ui(ui, |ui| {
+                    for name in self.full_source.channel_names() {
+                        ui.selectable_value(&mut channel, name.to_string(), name);
+                    }
+                });
+        });
+        if channel != self.channel {
+            let path = Path::new(path);
+            let mut settings = Settings::read(path);
+            settings.channel = Some(channel);
+            if let Err(e) = settings.write(path) {
+                log::error!("couldn't write the settings: {e}");
+            }
+            self.progress = Progress::NotUpdating;
+            self.refresh_channel();
+            self.refresh_current_version();
+        }
     }
 
     pub fn new(config: &PatcherConfig, source: Source) -> Self {
+        let new_release = source.patcher.as_ref().and_then(|release| {
+            let download = release.update_for(EXECUTABLE, env!("CARGO_PKG_VERSION"))?;
+            Some((release.version.clone(), download.clone()))
+        });
         let mut patcher = Self {
+            full_source: source.clone(),
             source,
+            channel: DEFAULT_CHANNEL.to_string(),
+            default_channel: config.channel.clone(),
             version: Version::NotFetched,
             progress: Progress::NotUpdating,
+            target: None,
             selected_path: config.get_default_path(),
             receiver: None,
+            cancellation: None,
             sub_progressbar_text: None,
             download_error: None,
+            components: Vec::new(),
+            new_release,
+            relaunch: None,
+            self_update_error: None,
+            conflicts: None,
+            history: None,
+            keep_backups: config.keep_backups,
+            last_backup: None,
+            rollback_error: None,
         };
+        patcher.refresh_channel();
         if patcher.selected_path.is_some() {
             patcher.refresh_current_version();
         }
         patcher
     }
 
-    fn execute_instructions_from_receiver(&mut self) {
+    fn execute_instructions_from_receiver(&mut self, ctx: &Context) {
         if let Some(rx) = &mut self.receiver {
             let mut stop_receive = false;
+            let mut refresh_version = false;
+            let mut start_update = None;
             while let Ok(action) = rx.try_recv() {
                 match action {
                     NewAction::Downloading(name) => {
-                        self.sub_progressbar_text = Some(format!("Téléchargement de la version {name}"));
+                        self.sub_progressbar_text =
+                            Some(format!("Téléchargement de la version {name}"));
                     }
                     NewAction::Patching(name) => {
-                        self.sub_progressbar_text = Some(format!("Application du patch sur le fichier {name}"));
+                        self.sub_progressbar_text =
+                            Some(format!("Application du patch sur le fichier {name}"));
                     }
                     NewAction::FinishSingleVersion => {
                         if let Progress::Updating { done, .. } = &mut self.progress {
@@ -126,9 +363,65 @@ impl Patcher {
                     }
                     NewAction::Finish => {
                         self.sub_progressbar_text = None;
+                        self.cancellation = None;
                         self.progress = Progress::Updated;
+                        self.history = None;
+                        refresh_version = true;
+                        stop_receive = true;
+                    }
+                    NewAction::Restored => {
+                        self.sub_progressbar_text = None;
+                        self.cancellation = None;
+                        self.progress = Progress::Restored;
+                        self.history = None;
+                        refresh_version = true;
+                        stop_receive = true;
+                    }
+                    NewAction::ConflictsFound {
+                        conflicts: Some(conflicts),
+                        versions_to_install,
+                    } if !conflicts.files.is_empty() => {
+                        self.conflicts = Some((*conflicts, versions_to_install));
+                        stop_receive = true;
+                    }
+                    NewAction::ConflictsFound {
+                        versions_to_install,
+                        ..
+                    } => {
+                        start_update = Some(versions_to_install);
+                        stop_receive = true;
+                    }
+                    NewAction::RolledBack => {
+                        self.rollback_error = None;
+                        self.history = None;
+                        refresh_version = true;
+                        stop_receive = true;
+                    }
+                    NewAction::RollbackError(error) => {
+                        self.rollback_error = Some(error);
+                        self.history = None;
+                        // Files may have been moved back before the error
+                        refresh_version = true;
+                        stop_receive = true;
+                    }
+                    NewAction::PatcherUpdated(executable) => {
+                        self.relaunch = Some(executable);
+                        stop_receive = true;
+                    }
+                    NewAction::SelfUpdateError(error) => {
+                        self.self_update_error = Some(error);
+                        stop_receive = true;
+                    }
+                    NewAction::DownloadAndPatchError(DownloadAndPatchError::Cancelled) => {
+                        self.sub_progressbar_text = None;
+                        self.cancellation = None;
+                        self.progress = Progress::Cancelled;
+                        // Previous transitions may have been applied before the cancellation
+                        refresh_version = true;
+                        stop_receive = true;
                     }
                     NewAction::DownloadAndPatchError(error) => {
+                        self.cancellation = None;
                         self.download_error = Some(error);
                         stop_receive = true;
                     }
@@ -137,6 +430,12 @@ impl Patcher {
             if stop_receive {
                 self.receiver = None;
             }
+            if refresh_version {
+                self.refresh_current_version();
+            }
+            if let Some(versions_to_install) = start_update {
+                self.start_update(ctx, versions_to_install, ConflictPolicy::Abort, None);
+            }
         }
     }
 
@@ -153,6 +452,7 @@ impl Patcher {
                 if ui.button("Revérifier").clicked() {
                     self.refresh_current_version();
                 }
+                self.full_install(ui);
             }
             Version::IoError(ref x) => {
                 ui.colored_label(Color32::RED, "Une erreur I/O est survenue.");
@@ -167,6 +467,7 @@ impl Patcher {
                 if self.source.versions.len() - 1 == version {
                     ui.label("Vous avez la dernière version !");
                 }
+                self.restore_vanilla(ui, version);
             }
         }
     }
@@ -177,7 +478,9 @@ impl Patcher {
             && let Some(path) = rfd::FileDialog::new().pick_folder()
         {
             self.progress = Progress::NotUpdating;
+            self.history = None;
             self.selected_path = Some(path.display().to_string());
+            self.refresh_channel();
             self.refresh_current_version();
         }
         if let Some(old) = &self.selected_path {
@@ -186,78 +489,600 @@ impl Patcher {
         }
     }
 
-    #[allow(clippy::cast_possible_truncation)]
+    fn rollback(&mut self, ui: &mut Ui) {
+        if self.receiver.is_some() {
+            return;
+        }
+        if let Some(ref path) = self.selected_path
+            && let Some(ref backup) = self.last_backup
+            && ui
+                .button(format!("Revenir à la version {}", backup.from))
+                .clicked()
+        {
+            let path = path.clone();
+            let source = self.source.clone();
+            self.spawn_rollback(ui.ctx(), path, source);
+        }
+        if let Some(ref error) = self.rollback_error {
+            ui.colored_label(
+                Color32::RED,
+                "La version précédente n'a pas pu être restaurée.",
+            );
+            ui.code(RichText::new(error.to_string()).color(Color32::RED));
+        }
+    }
+
+    ///
+    /// Restores the last backup of `path` for the version chain `source` on a worker thread
+    ///
+    fn spawn_rollback(&mut self, ctx: &Context, path: String, source: Source) {
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        self.receiver = Some(rx);
+        self.progress = Progress::NotUpdating;
+        std::thread::spawn(move || {
+            let action = match patcher_common::backup::rollback(Path::new(&path), &source) {
+                Ok(_) => NewAction::RolledBack,
+                Err(e) => {
+                    log::error!("error while rolling back: {e}");
+                    NewAction::RollbackError(e)
+                }
+            };
+            let _ = tx.send(action);
+            ctx.request_repaint();
+        });
+    }
+
+    fn target_selector(&mut self, ui: &mut Ui, current_version: usize) {
+        let reachable = self.source.reachable_versions(current_version);
+        let older = self.source.reachable_older_versions(current_version);
+        if reachable.is_empty() && older.is_empty() {
+            return;
+        }
+        let latest = reachable.last().copied();
+        if self.target.is_none() {
+            self.target = latest;
+        }
+        let name = |version: usize| {
+            let name = &self.source.versions[version].name;
+            if Some(version) == latest {
+                format!("{name} (dernière)")
+            } else if older.contains(&version) {
+                format!("{name} (retour en arrière)")
+            } else {
+                name.clone()
+            }
+        };
+        ui.horizontal(|ui| {
+            ui.label("Version à installer :");
+            ComboBox::from_id_salt("target_version")
+                .selected_text(self.target.map(name).unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    for &version in older.iter().chain(&reachable) {
+                        ui.selectable_value(&mut self.target, Some(version), name(version));
+                    }
+                });
+        });
+    }
+
+    ///
+    /// Offers to install a clean copy of the most recent version having a full archive, when no
+    /// version was detected
+    ///
+    fn full_install(&mut self, ui: &mut Ui) {
+        if self.receiver.is_some() {
+            return;
+        }
+        let Some(ref path) = self.selected_path else {
+            return;
+        };
+        let Some(version) = self.source.latest_full_install() else {
+            return;
+        };
+        let version = self.source.versions[version].clone();
+        if !ui
+            .button(format!(
+                "Télécharger une copie complète de la version {}",
+                version.name
+            ))
+            .clicked()
+        {
+            return;
+        }
+        let path = path.clone();
+        let (tx, rx) = mpsc::channel();
+        let ctx = ui.ctx().clone();
+        let cancel = CancellationToken::new();
+        self.cancellation = Some(cancel.clone());
+        self.receiver = Some(rx);
+        self.download_error = None;
+        self.progress = Progress::Updating { done: 0, out_of: 1 };
+        std::thread::spawn(move || {
+            let res = patcher_common::download::download_full_install(
+                Path::new(&path),
+                &version,
+                &cancel,
+                ProgressTracker {
+                    ctx,
+                    tx: tx.clone(),
+                },
+            );
+            if let Err(e) = res {
+                log::error!("error while downloading the full install: {e}");
+                let _ = tx.send(NewAction::DownloadAndPatchError(e));
+            }
+        });
+    }
+
+    ///
+    /// Offers to bring the install directory back to a clean copy of the detected `version`, when
+    /// it has a manifest
+    ///
+    fn restore_vanilla(&mut self, ui: &mut Ui, version: usize) {
+        if self.receiver.is_some() || self.conflicts.is_some() {
+            return;
+        }
+        let Some(ref path) = self.selected_path else {
+            return;
+        };
+        let version = &self.source.versions[version];
+        if version.manifest.is_none()
+            || !ui
+                .button("Restaurer la version d'origine")
+                .on_hover_text(
+                    "Les fichiers ajoutés ou modifiés sont déplacés dans .thl-patcher/conflicts",
+                )
+                .clicked()
+        {
+            return;
+        }
+        let path = path.clone();
+        let version = version.clone();
+        let components: Vec<_> = self
+            .components
+            .iter()
+            .filter_map(|x| Some(x.chain.versions[x.version?].clone()))
+            .collect();
+        let (tx, rx) = mpsc::channel();
+        let ctx = ui.ctx().clone();
+        let cancel = CancellationToken::new();
+        self.cancellation = Some(cancel.clone());
+        self.receiver = Some(rx);
+        self.download_error = None;
+        self.progress = Progress::Updating { done: 0, out_of: 1 };
+        self.sub_progressbar_text = Some(format!("Restauration de la version {}", version.name));
+        std::thread::spawn(move || {
+            let action = match patcher_common::manifest::restore_vanilla(
+                Path::new(&path),
+                &version,
+                &components,
+                &cancel,
+            ) {
+                Ok(restoration) => {
+                    log::info!(
+                        "restored version {}: {} file(s) removed, {} file(s) restored",
+                        version.name,
+                        restoration.removed.len(),
+                        restoration.restored.len()
+                    );
+                    NewAction::Restored
+                }
+                Err(e) => {
+                    log::error!("error while restoring the version: {e}");
+                    NewAction::DownloadAndPatchError(e)
+                }
+            };
+            let _ = tx.send(action);
+            ctx.request_repaint();
+        });
+    }
+
+    ///
+    /// Shows the release notes of the versions brought by the update, and the download size
+    ///
+    fn changelog_panel(&self, ui: &mut Ui, current_version: usize, target: usize) {
+        let versions = self.source.changes(current_version, target);
+        ScrollArea::vertical()
+            .id_salt("changelog")
+            .max_height(150.)
+            .show(ui, |ui| {
+                for version in versions.iter().rev() {
+                    let title = version.formatted_release_date().map_or_else(
+                        || version.name.clone(),
+                        |date| format!("{} ({date})", version.name),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.strong(title);
+                        match version.importance {
+                            Some(Importance::Critical) => {
+                                ui.colored_label(Color32::RED, "critique");
+                            }
+                            Some(Importance::Recommended) => {
+                                ui.colored_label(Color32::YELLOW, "recommandée");
+                            }
+                            Some(Importance::Optional) => {
+                                ui.label("facultative");
+                            }
+                            None => (),
+                        }
+                    });
+                    if let Some(changelog) = &version.changelog {
+                        ui.label(changelog);
+                    }
+                    ui.separator();
+                }
+            });
+        let size: Option<u64> = self
+            .source
+            .plan(current_version, target)
+            .unwrap_or_default()
+            .iter()
+            .map(|transition| transition.patch?.archive_size)
+            .sum();
+        if let Some(size) = size {
+            ui.label(format!(
+                "Taille du téléchargement : {} Mo",
+                size / 1_000_000
+            ));
+        }
+    }
+
     fn apply_patch(&mut self, ui: &mut Ui) {
-        if let Some(ref old) = self.selected_path
+        if self.selected_path.is_some()
             && let Version::Found(current_version) = self.version
+            && self.receiver.is_none()
+        {
+            self.target_selector(ui, current_version);
+            if let Some(target) = self.target {
+                self.changelog_panel(ui, current_version, target);
+            }
+            ui.checkbox(
+                &mut self.keep_backups,
+                "Conserver une sauvegarde des fichiers remplacés",
+            );
+        }
+        if self.conflicts.is_some() {
+            self.conflicts_panel(ui);
+        } else if let Some(ref old) = self.selected_path
+            && let Version::Found(current_version) = self.version
+            && let Some(target) = self.target
+            && self.receiver.is_none()
             && ui.button("Appliquer le Patch").clicked()
         {
-            let versions_to_install: Vec<_> = self
+            let versions_to_install = self
                 .source
-                .get_transitions(current_version)
-                .map(|x| x.to_owned())
+                .plan(current_version, target)
+                .unwrap_or_default()
+                .iter()
+                .map(VersionTransitionRef::to_owned)
                 .collect();
             let old = old.clone();
-            let (tx, rx) = mpsc::channel();
-            let ctx = ui.ctx().clone();
-            self.receiver = Some(rx);
-            self.progress = Progress::Updating { done: 0, out_of: versions_to_install.len() as u32 };
-            std::thread::spawn(move || {
-                let res = patcher_common::download::download_and_patch(
-                    Path::new(&old),
-                    versions_to_install.iter().map(VersionTransition::as_ref),
-                    ProgressTracker {
-                        ctx,
-                        tx: tx.clone()
-                    }
+            self.find_conflicts(ui.ctx(), old, versions_to_install);
+        }
+    }
+
+    ///
+    /// Looks for the files of `old` modified by the user on a worker thread, before applying
+    /// `versions_to_install`
+    ///
+    fn find_conflicts(
+        &mut self,
+        ctx: &Context,
+        old: String,
+        versions_to_install: Vec<VersionTransition>,
+    ) {
+        let Some(first) = versions_to_install.first() else {
+            return;
+        };
+        // Every file of the manifest is hashed, which can take a while
+        let version = first.old.clone();
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        self.download_error = None;
+        self.receiver = Some(rx);
+        std::thread::spawn(move || {
+            let action = match Conflicts::find(Path::new(&old), &version) {
+                Ok(conflicts) => NewAction::ConflictsFound {
+                    conflicts: conflicts.map(Box::new),
+                    versions_to_install,
+                },
+                Err(e) => {
+                    log::error!("error while looking for modified files: {e}");
+                    NewAction::DownloadAndPatchError(e)
+                }
+            };
+            let _ = tx.send(action);
+            ctx.request_repaint();
+        });
+    }
+
+    ///
+    /// Lists the files modified by the user, and lets them choose what to do with them
+    ///
+    fn conflicts_panel(&mut self, ui: &mut Ui) {
+        let Some((conflicts, _)) = &self.conflicts else {
+            return;
+        };
+        ui.colored_label(Color32::YELLOW, "Les fichiers suivants ont été modifiés :");
+        for file in &conflicts.files {
+            ui.monospace(file.display().to_string());
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Annuler").clicked() {
+                self.conflicts = None;
+            }
+            if ui
+                .button("Remplacer (avec sauvegarde)")
+                .on_hover_text("Les fichiers modifiés sont déplacés dans .thl-patcher/conflicts")
+                .clicked()
+                && let Some((conflicts, versions_to_install)) = self.conflicts.take()
+            {
+                self.start_update(
+                    ui.ctx(),
+                    versions_to_install,
+                    ConflictPolicy::Overwrite,
+                    Some(conflicts),
+                );
+            }
+            if ui
+                .button("Ignorer ces fichiers")
+                .on_hover_text("Les fichiers modifiés ne seront pas mis à jour")
+                .clicked()
+                && let Some((conflicts, versions_to_install)) = self.conflicts.take()
+            {
+                self.start_update(
+                    ui.ctx(),
+                    versions_to_install,
+                    ConflictPolicy::Skip,
+                    Some(conflicts),
                 );
-                match res {
-                    Ok(()) => (),
-                    Err(e) => {
-                        log::error!("error while downloading the patch or applying it: {e}");
-                        let _ = tx.send(NewAction::DownloadAndPatchError(e));
+            }
+        });
+    }
+
+    ///
+    /// Applies `versions_to_install` on the selected directory, on a worker thread
+    ///
+    #[allow(clippy::cast_possible_truncation)]
+    fn start_update(
+        &mut self,
+        ctx: &Context,
+        versions_to_install: Vec<VersionTransition>,
+        conflict_policy: ConflictPolicy,
+        conflicts: Option<Conflicts>,
+    ) {
+        let Some(old) = self.selected_path.clone() else {
+            return;
+        };
+        self.download_error = None;
+        let options = PatchOptions {
+            keep_backups: self.keep_backups,
+            conflict_policy,
+            conflicts,
+        };
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        let cancel = CancellationToken::new();
+        self.cancellation = Some(cancel.clone());
+        self.receiver = Some(rx);
+        self.progress = Progress::Updating {
+            done: 0,
+            out_of: versions_to_install.len() as u32,
+        };
+        std::thread::spawn(move || {
+            let res = patcher_common::download::download_and_patch(
+                Path::new(&old),
+                versions_to_install.iter().map(VersionTransition::as_ref),
+                &options,
+                &cancel,
+                ProgressTracker {
+                    ctx,
+                    tx: tx.clone(),
+                },
+            );
+            match res {
+                Ok(()) => (),
+                Err(e) => {
+                    log::error!("error while downloading the patch or applying it: {e}");
+                    let _ = tx.send(NewAction::DownloadAndPatchError(e));
+                }
+            }
+        });
+    }
+
+    ///
+    /// Lists the optional components of the source, with the version installed of each, and
+    /// offers to update the installed ones to their latest version
+    ///
+    fn components_panel(&mut self, ui: &mut Ui) {
+        if self.components.is_empty() {
+            return;
+        }
+        let Some(path) = self.selected_path.clone() else {
+            return;
+        };
+        let mut update = None;
+        let mut rollback = None;
+        ui.collapsing("Composants optionnels", |ui| {
+            for component in &self.components {
+                ui.horizontal(|ui| {
+                    ui.strong(&component.name);
+                    if let Some(description) = &component.description {
+                        ui.label(description);
+                    }
+                });
+                let Some(current_version) = component.version else {
+                    ui.label("Non installé");
+                    continue;
+                };
+                ui.label(format!(
+                    "Version actuelle : {}",
+                    component.chain.versions[current_version].name
+                ));
+                let transitions = component.chain.plan_to_latest(current_version);
+                match transitions.last() {
+                    None => {
+                        ui.label("Ce composant est à jour.");
+                    }
+                    Some(last) => {
+                        if self.receiver.is_none()
+                            && self.conflicts.is_none()
+                            && ui
+                                .button(format!("Mettre à jour vers {}", last.new.name))
+                                .clicked()
+                        {
+                            update = Some(
+                                transitions
+                                    .iter()
+                                    .map(VersionTransitionRef::to_owned)
+                                    .collect(),
+                            );
+                        }
                     }
                 }
-            });
+                if let Some(backup) = &component.last_backup
+                    && self.receiver.is_none()
+                    && ui
+                        .button(format!("Revenir à la version {}", backup.from))
+                        .clicked()
+                {
+                    rollback = Some(component.chain.clone());
+                }
+                ui.separator();
+            }
+        });
+        if let Some(versions_to_install) = update {
+            self.find_conflicts(ui.ctx(), path, versions_to_install);
+        } else if let Some(chain) = rollback {
+            self.spawn_rollback(ui.ctx(), path, chain);
         }
     }
 
     fn progress_bars(&self, ui: &mut Ui) {
         match self.progress {
-            Progress::Updating {
-                done,
-                out_of
-            } => {
+            Progress::Updating { done, out_of } => {
                 #[allow(clippy::cast_precision_loss)]
                 ui.add(ProgressBar::new(done as f32 / out_of as f32).show_percentage());
                 if let Some(text) = self.sub_progressbar_text.as_ref() {
                     ui.code(text);
                 }
+                if let Some(cancel) = &self.cancellation {
+                    if cancel.is_cancelled() {
+                        ui.label("Annulation en cours...");
+                    } else if ui.button("Annuler").clicked() {
+                        cancel.cancel();
+                    }
+                }
             }
             Progress::NotUpdating => (),
             Progress::Updated => {
                 ui.label("Mise à jour complétée avec succès !");
             }
+            Progress::Restored => {
+                ui.label("La version d'origine a été restaurée.");
+            }
+            Progress::Cancelled => {
+                ui.label("Mise à jour annulée.");
+            }
         }
     }
 
     fn display_error(&self, ui: &mut Ui) {
         if let Some(ref error) = self.download_error {
             ui.code(RichText::new(error.to_string()).color(Color32::RED));
+            match error {
+                DownloadAndPatchError::BlockedFiles(files) => {
+                    ui.colored_label(Color32::RED, "Vérifiez que le jeu est bien fermé.");
+                    for file in files {
+                        ui.monospace(format!("{} ({})", file.path.display(), file.error));
+                    }
+                }
+                DownloadAndPatchError::Conflicts(files) => {
+                    for file in files {
+                        ui.monospace(file.display().to_string());
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn history_panel(&mut self, ui: &mut Ui) {
+        let Some(path) = &self.selected_path else {
+            return;
+        };
+        if self.history.is_none() {
+            if ui.button("Afficher l'historique").clicked() {
+                self.history =
+                    Some(patcher_common::history::read(Path::new(path)).map_err(|e| e.to_string()));
+            }
+            return;
+        }
+        if ui.button("Masquer l'historique").clicked() {
+            self.history = None;
+            return;
+        }
+        match &self.history {
+            Some(Ok(entries)) if entries.is_empty() => {
+                ui.label("Aucune mise à jour n'a encore été effectuée sur ce dossier.");
+            }
+            Some(Ok(entries)) => {
+                ScrollArea::vertical().max_height(150.).show(ui, |ui| {
+                    for entry in entries.iter().rev() {
+                        let (outcome, color) = match entry.outcome {
+                            Outcome::Success => ("réussie", Color32::GREEN),
+                            Outcome::Cancelled => ("annulée", Color32::YELLOW),
+                            Outcome::Failed => ("échouée", Color32::RED),
+                        };
+                        let mut transition = if entry.is_full_install() {
+                            format!("installation complète de {}", entry.to)
+                        } else if entry.is_restoration() {
+                            format!("restauration de {}", entry.to)
+                        } else {
+                            format!("{} → {}", entry.from, entry.to)
+                        };
+                        if let Some(component) = &entry.component {
+                            transition = format!("{component} : {transition}");
+                        }
+                        ui.colored_label(
+                            color,
+                            format!(
+                                "[{}] {transition} : {outcome} ({} fichier(s))",
+                                entry.formatted_timestamp(),
+                                entry.files.len()
+                            ),
+                        );
+                        if let Some(error) = &entry.error {
+                            ui.code(RichText::new(error).color(Color32::RED));
+                        }
+                    }
+                });
+            }
+            Some(Err(e)) => {
+                ui.colored_label(Color32::RED, "L'historique n'a pas pu être lu.");
+                ui.code(RichText::new(e).color(Color32::RED));
+            }
+            None => (),
         }
     }
 
     pub fn update(&mut self, ui: &mut Ui) {
-        self.execute_instructions_from_receiver();
+        self.execute_instructions_from_receiver(ui.ctx());
 
         ui.vertical_centered(|ui| {
             ui.heading("Gestionnaire de Mise à Jour");
+            self.patcher_update_panel(ui);
             self.show_version(ui);
             ui.add_space(15.);
             self.file_selector(ui);
+            self.channel_selector(ui);
             self.apply_patch(ui);
+            self.rollback(ui);
+            self.components_panel(ui);
             self.progress_bars(ui);
             self.display_error(ui);
+            ui.add_space(15.);
+            self.history_panel(ui);
         });
     }
 }
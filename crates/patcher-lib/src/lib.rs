@@ -1,3 +1,34 @@
+pub use cancel::*;
+
+mod cancel {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    /// A token shared between the caller and a long running operation, allowing the caller to
+    /// request its interruption. Operations check it between files and stop as soon as possible.
+    #[derive(Debug, Default, Clone)]
+    pub struct CancellationToken {
+        cancelled: Arc<AtomicBool>,
+    }
+
+    impl CancellationToken {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Requests the cancellation of every operation holding a clone of this token
+        pub fn cancel(&self) {
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+
+        pub fn is_cancelled(&self) -> bool {
+            self.cancelled.load(Ordering::Relaxed)
+        }
+    }
+}
+
 #[cfg(feature = "diff")]
 pub use diff::*;
 
@@ -12,6 +43,8 @@ mod diff {
     use thiserror::Error;
     use walkdir::WalkDir;
 
+    use crate::CancellationToken;
+
     const CHUNK_SIZE: usize = 400_000_000;
 
     fn count_files(path: &Path) -> usize {
@@ -37,12 +70,34 @@ mod diff {
         DdeltaDiff(#[from] ddelta::DiffError),
         #[error("old and new should both be files or dir")]
         TypeMismatch,
+        #[error("operation cancelled")]
+        Cancelled,
     }
 
-    pub fn diff_in_tar(
+    pub(crate) fn append_delta(
+        from: &Path,
+        to: &Path,
+        file_relative_path: &Path,
+        destination: &mut tar::Builder<impl Write>,
+    ) -> Result<(), DiffError> {
+        let mut tmp_file = NamedTempFile::new()?;
+        ddelta::generate_chunked(
+            &mut BufReader::new(File::open(from)?),
+            &mut BufReader::new(File::open(to)?),
+            &mut BufWriter::new(&mut tmp_file),
+            CHUNK_SIZE,
+            |_| (),
+        )?;
+        destination.append_file(file_relative_path, tmp_file.as_file_mut())?;
+        Ok(())
+    }
+
+    fn diff_in_tars(
         old: &Path,
         new: &Path,
         destination: &mut tar::Builder<impl Write>,
+        mut reverse: Option<&mut tar::Builder<impl Write>>,
+        cancel: &CancellationToken,
         mut update: impl FnMut(DiffState),
     ) -> Result<(), DiffError> {
         if old.is_dir() && new.is_dir() {
@@ -52,6 +107,9 @@ mod diff {
             };
 
             for file in WalkDir::new(new) {
+                if cancel.is_cancelled() {
+                    return Err(DiffError::Cancelled);
+                }
                 let file = file?;
                 if !file.file_type().is_file() {
                     continue;
@@ -67,15 +125,15 @@ mod diff {
                     continue;
                 }
 
-                let mut tmp_file = NamedTempFile::new()?;
-                ddelta::generate_chunked(
-                    &mut BufReader::new(File::open(&old_file_path)?),
-                    &mut BufReader::new(File::open(&new_file_path)?),
-                    &mut BufWriter::new(&mut tmp_file),
-                    CHUNK_SIZE,
-                    |_| (),
+                append_delta(
+                    &old_file_path,
+                    &new_file_path,
+                    file_relative_path,
+                    destination,
                 )?;
-                destination.append_file(file_relative_path, tmp_file.as_file_mut())?;
+                if let Some(reverse) = reverse.as_deref_mut() {
+                    append_delta(&new_file_path, &old_file_path, file_relative_path, reverse)?;
+                }
                 state.done += 1;
                 (update)(state)
             }
@@ -84,6 +142,38 @@ mod diff {
             Err(DiffError::TypeMismatch)
         }
     }
+
+    pub fn diff_in_tar(
+        old: &Path,
+        new: &Path,
+        destination: &mut tar::Builder<impl Write>,
+        cancel: &CancellationToken,
+        update: impl FnMut(DiffState),
+    ) -> Result<(), DiffError> {
+        diff_in_tars(
+            old,
+            new,
+            destination,
+            None::<&mut tar::Builder<io::Sink>>,
+            cancel,
+            update,
+        )
+    }
+
+    ///
+    /// Same as [`diff_in_tar`], but also writes the reverse delta (from `new` to `old`) in
+    /// `reverse`, allowing to go back to `old` with the same patching process
+    ///
+    pub fn diff_in_tar_with_reverse(
+        old: &Path,
+        new: &Path,
+        destination: &mut tar::Builder<impl Write>,
+        reverse: &mut tar::Builder<impl Write>,
+        cancel: &CancellationToken,
+        update: impl FnMut(DiffState),
+    ) -> Result<(), DiffError> {
+        diff_in_tars(old, new, destination, Some(reverse), cancel, update)
+    }
 }
 
 #[cfg(feature = "patch")]
@@ -97,6 +187,8 @@ mod patch {
     };
     use thiserror::Error;
 
+    use crate::CancellationToken;
+
     pub struct CurrentPatchingPath {
         pub path: PathBuf,
     }
@@ -109,18 +201,42 @@ mod patch {
         DdeltaPatch(#[from] ddelta::PatchError),
         #[error("old and new should both be files or dir")]
         TypeMismatch,
+        #[error("operation cancelled")]
+        Cancelled,
     }
 
     pub fn patch_from_tar(
         old: &Path,
         new: &mut tar::Archive<impl Read>,
         destination: &Path,
+        cancel: &CancellationToken,
+        update: impl FnMut(CurrentPatchingPath),
+    ) -> Result<(), PatchError> {
+        patch_from_tar_filtered(old, new, destination, cancel, |_| true, update)
+    }
+
+    ///
+    /// Like [`patch_from_tar`], but only the files of the archive for which `filter` returns
+    /// `true` are patched
+    ///
+    pub fn patch_from_tar_filtered(
+        old: &Path,
+        new: &mut tar::Archive<impl Read>,
+        destination: &Path,
+        cancel: &CancellationToken,
+        filter: impl Fn(&Path) -> bool,
         mut update: impl FnMut(CurrentPatchingPath),
     ) -> Result<(), PatchError> {
         if old.is_dir() && (destination.is_dir() || !destination.exists()) {
             std::fs::create_dir_all(destination)?;
             for file in new.entries()? {
+                if cancel.is_cancelled() {
+                    return Err(PatchError::Cancelled);
+                }
                 let file = file?;
+                if !filter(&file.path()?) {
+                    continue;
+                }
                 (update)(CurrentPatchingPath {
                     path: file.path()?.into_owned(),
                 });
@@ -153,3 +269,298 @@ mod patch {
         }
     }
 }
+
+#[cfg(all(feature = "diff", feature = "patch"))]
+pub use compose::*;
+#[cfg(all(feature = "diff", feature = "patch"))]
+mod compose {
+    use std::{
+        collections::BTreeSet,
+        fs::File,
+        io::{self, BufReader, BufWriter, Read, Write},
+        path::{Path, PathBuf},
+    };
+    use tempfile::{NamedTempFile, tempdir};
+    use thiserror::Error;
+
+    use crate::{CancellationToken, DiffError, DiffState, diff::append_delta};
+
+    pub enum ComposeState {
+        /// A file of the archive `archive` (counting from 0) is being applied
+        Patching { archive: usize, path: PathBuf },
+        /// The cumulative delta of a file is being generated
+        Diffing(DiffState),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum ComposeError {
+        #[error("io error: {0}")]
+        Io(#[from] io::Error),
+        #[error("error with ddelta patch")]
+        DdeltaPatch(#[from] ddelta::PatchError),
+        #[error("diff error: {0}")]
+        Diff(#[from] DiffError),
+        #[error("base should be a dir")]
+        TypeMismatch,
+        #[error("operation cancelled")]
+        Cancelled,
+    }
+
+    ///
+    /// Applies the `patches` one after the other on top of `base`, and writes a single archive
+    /// going directly from `base` to the result in `destination`.
+    ///
+    /// Only the files touched by at least one patch are re-diffed against `base`, and `base`
+    /// itself is never modified.
+    ///
+    pub fn compose_in_tar<R: Read>(
+        base: &Path,
+        patches: impl IntoIterator<Item = tar::Archive<R>>,
+        destination: &mut tar::Builder<impl Write>,
+        cancel: &CancellationToken,
+        mut update: impl FnMut(ComposeState),
+    ) -> Result<(), ComposeError> {
+        if !base.is_dir() {
+            return Err(ComposeError::TypeMismatch);
+        }
+        // Holds the latest version of every touched file
+        let work = tempdir()?;
+        let mut touched = BTreeSet::new();
+
+        for (i, mut patch) in patches.into_iter().enumerate() {
+            for file in patch.entries()? {
+                if cancel.is_cancelled() {
+                    return Err(ComposeError::Cancelled);
+                }
+                let file = file?;
+                let suffix = file.path()?.into_owned();
+                (update)(ComposeState::Patching {
+                    archive: i,
+                    path: suffix.clone(),
+                });
+
+                let current = if touched.contains(&suffix) {
+                    work.path().join(&suffix)
+                } else {
+                    base.join(&suffix)
+                };
+                if !current.exists() {
+                    tracing::warn!("ignoring {}", suffix.display());
+                    continue;
+                }
+
+                let in_work = work.path().join(&suffix);
+                let parent = match in_work.parent() {
+                    Some(x) => x,
+                    None => unreachable!("in_work should always have a parent"),
+                };
+                std::fs::create_dir_all(parent)?;
+                let mut patched = NamedTempFile::new_in(work.path())?;
+                ddelta::apply_chunked(
+                    &mut BufReader::new(File::open(&current)?),
+                    &mut BufWriter::new(patched.as_file_mut()),
+                    &mut BufReader::new(file),
+                )?;
+                patched.persist(in_work).map_err(|e| e.error)?;
+                touched.insert(suffix);
+            }
+        }
+
+        let mut state = DiffState {
+            done: 0,
+            out_of: touched.len(),
+        };
+        for suffix in touched {
+            if cancel.is_cancelled() {
+                return Err(ComposeError::Cancelled);
+            }
+            append_delta(
+                &base.join(&suffix),
+                &work.path().join(&suffix),
+                &suffix,
+                destination,
+            )?;
+            state.done += 1;
+            (update)(ComposeState::Diffing(state));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "diff", feature = "patch"))]
+mod tests {
+    use std::path::Path;
+
+    use tempfile::tempdir;
+
+    use crate::{
+        CancellationToken, ComposeError, DiffError, PatchError, compose_in_tar, diff_in_tar,
+        patch_from_tar,
+    };
+
+    fn write_files(dir: &Path, files: &[(&str, &str)]) {
+        for (name, content) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+    }
+
+    fn diff(old: &Path, new: &Path) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        diff_in_tar(old, new, &mut builder, &CancellationToken::new(), |_| ()).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn composed_patch_goes_to_the_last_version() {
+        let (v1, v2, v3) = (tempdir().unwrap(), tempdir().unwrap(), tempdir().unwrap());
+        write_files(
+            v1.path(),
+            &[
+                ("a", "a1"),
+                ("data/b", "b1"),
+                ("c", "c1"),
+                ("only_in_base", "o"),
+            ],
+        );
+        write_files(v2.path(), &[("a", "a2"), ("data/b", "b1"), ("c", "c2")]);
+        write_files(v3.path(), &[("a", "a3"), ("data/b", "b3"), ("c", "c2")]);
+        let patches = [diff(v1.path(), v2.path()), diff(v2.path(), v3.path())];
+
+        let mut composed = tar::Builder::new(Vec::new());
+        compose_in_tar(
+            v1.path(),
+            patches.iter().map(|x| tar::Archive::new(x.as_slice())),
+            &mut composed,
+            &CancellationToken::new(),
+            |_| (),
+        )
+        .unwrap();
+        let composed = composed.into_inner().unwrap();
+
+        // The base is left untouched
+        assert_eq!(std::fs::read_to_string(v1.path().join("a")).unwrap(), "a1");
+
+        let destination = tempdir().unwrap();
+        patch_from_tar(
+            v1.path(),
+            &mut tar::Archive::new(composed.as_slice()),
+            destination.path(),
+            &CancellationToken::new(),
+            |_| (),
+        )
+        .unwrap();
+        for (file, expected) in [("a", "a3"), ("data/b", "b3"), ("c", "c2")] {
+            assert_eq!(
+                std::fs::read_to_string(destination.path().join(file)).unwrap(),
+                expected
+            );
+        }
+        // Only the files touched by at least one patch are in the composed archive
+        assert!(!destination.path().join("only_in_base").exists());
+    }
+
+    fn archive(files: &[&str]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for file in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(5);
+            builder
+                .append_data(&mut header, file, &b"delta"[..])
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn cancelled_patch_writes_nothing() {
+        let (old, destination) = (tempdir().unwrap(), tempdir().unwrap());
+        write_files(old.path(), &[("a", "a1")]);
+        let patch = archive(&["a"]);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let res = patch_from_tar(
+            old.path(),
+            &mut tar::Archive::new(patch.as_slice()),
+            destination.path(),
+            &cancel,
+            |_| panic!("no file should be patched"),
+        );
+        assert!(matches!(res, Err(PatchError::Cancelled)));
+        assert!(!destination.path().join("a").exists());
+    }
+
+    #[test]
+    fn patch_stops_before_the_next_file() {
+        let (old, destination) = (tempdir().unwrap(), tempdir().unwrap());
+        write_files(old.path(), &[("a", "a1")]);
+        // `missing` isn't in `old`, so it's skipped without being patched
+        let patch = archive(&["missing", "a"]);
+
+        let cancel = CancellationToken::new();
+        let mut reported = Vec::new();
+        let res = patch_from_tar(
+            old.path(),
+            &mut tar::Archive::new(patch.as_slice()),
+            destination.path(),
+            &cancel,
+            |current| {
+                reported.push(current.path);
+                cancel.cancel();
+            },
+        );
+        assert!(matches!(res, Err(PatchError::Cancelled)));
+        assert_eq!(reported, [Path::new("missing")]);
+        assert!(!destination.path().join("a").exists());
+    }
+
+    #[test]
+    fn cancelled_diff_writes_nothing() {
+        let (old, new) = (tempdir().unwrap(), tempdir().unwrap());
+        write_files(old.path(), &[("a", "a1")]);
+        write_files(new.path(), &[("a", "a2")]);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let mut builder = tar::Builder::new(Vec::new());
+        let res = diff_in_tar(old.path(), new.path(), &mut builder, &cancel, |_| ());
+        assert!(matches!(res, Err(DiffError::Cancelled)));
+        let patch = builder.into_inner().unwrap();
+        let mut archive = tar::Archive::new(patch.as_slice());
+        assert_eq!(archive.entries().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn compose_is_cancellable() {
+        let (v1, v2) = (tempdir().unwrap(), tempdir().unwrap());
+        write_files(v1.path(), &[("a", "a1")]);
+        write_files(v2.path(), &[("a", "a2")]);
+        let patch = diff(v1.path(), v2.path());
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let res = compose_in_tar(
+            v1.path(),
+            [tar::Archive::new(patch.as_slice())],
+            &mut tar::Builder::new(Vec::new()),
+            &cancel,
+            |_| (),
+        );
+        assert!(matches!(res, Err(ComposeError::Cancelled)));
+    }
+
+    #[test]
+    fn compose_needs_a_base_directory() {
+        let base = tempdir().unwrap();
+        let res = compose_in_tar(
+            &base.path().join("missing"),
+            std::iter::empty::<tar::Archive<&[u8]>>(),
+            &mut tar::Builder::new(Vec::new()),
+            &CancellationToken::new(),
+            |_| (),
+        );
+        assert!(matches!(res, Err(ComposeError::TypeMismatch)));
+    }
+}
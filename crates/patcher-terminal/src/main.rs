@@ -3,10 +3,23 @@ use std::process::ExitCode;
 
 use colored::Colorize;
 use indicatif::{MultiProgress, ProgressBar};
-use patcher_common::download::ProgressReporter;
+use patcher_common::backup::Backup;
+use patcher_common::download::{PatchOptions, ProgressReporter};
+use patcher_common::error::DownloadAndPatchError;
+use patcher_common::history::Outcome;
+use patcher_common::manifest::{ConflictPolicy, Conflicts};
+use patcher_common::self_update::{relaunch, remove_previous_executable, self_update};
+use patcher_common::settings::Settings;
+use patcher_common::structures::channel::DEFAULT_CHANNEL;
 
-use patcher_common::structures::{config::PatcherConfig, source::Source};
+use patcher_common::structures::{
+    config::PatcherConfig,
+    source::{Importance, Source, Version, VersionTransitionRef},
+};
 use rustyline::DefaultEditor;
+use thl_patcher::CancellationToken;
+
+const EXECUTABLE: &str = "patcher-terminal";
 
 fn get_config() -> PatcherConfig {
     if let Ok(file) = std::fs::read_to_string("config.yaml")
@@ -48,6 +61,11 @@ impl ProgressReporter for Progress {
             .set_message(format!("downloading {}", transition.new.name));
     }
 
+    fn on_start_full_install(&mut self, version: &Version) {
+        self.sub
+            .set_message(format!("downloading {}", version.name));
+    }
+
     fn on_patching_file(&mut self, path: &Path) {
         self.sub.set_message(format!("patching {}", path.display()));
     }
@@ -70,19 +88,30 @@ pub enum PatcherError {
     UpdateFailed,
 }
 
-fn inner(rl: &mut DefaultEditor) -> ExitCode {
-    let config = get_config();
-    let source = match Source::from_url(&config.source) {
-        Ok(s) => {
-            log::debug!("source fetched successfully");
-            s
-        }
-        Err(e) => {
-            log::error!("error while fetching source: {e}");
-            return ExitCode::FAILURE;
+pub enum Command {
+    Update,
+    History,
+    Rollback,
+    Channel,
+    Restore,
+    Components,
+}
+
+impl Command {
+    fn from_args() -> Option<Self> {
+        match std::env::args().nth(1).as_deref() {
+            None | Some("update") => Some(Self::Update),
+            Some("history") => Some(Self::History),
+            Some("rollback") => Some(Self::Rollback),
+            Some("channel") => Some(Self::Channel),
+            Some("restore") => Some(Self::Restore),
+            Some("components") => Some(Self::Components),
+            Some(_) => None,
         }
-    };
+    }
+}
 
+fn ask_path(rl: &mut DefaultEditor, config: &PatcherConfig) -> Option<String> {
     println!("Écrivez le chemin vers le dossier de votre jeu.");
     let s = match config.get_default_path() {
         Some(x) => {
@@ -95,24 +124,604 @@ fn inner(rl: &mut DefaultEditor) -> ExitCode {
         None => rl.readline("> "),
     };
 
-    let path = match s {
-        Ok(s) => s,
+    match s {
+        Ok(s) => Some(s),
         Err(e) => {
             log::error!("error when trying to read line: {e}");
+            None
+        }
+    }
+}
+
+fn confirm(rl: &mut DefaultEditor) -> Option<bool> {
+    loop {
+        match rl.readline("> ") {
+            Ok(x) if ["y", "yes", "o", "oui"].contains(&x.as_str()) => return Some(true),
+            Ok(x) if ["n", "no", "non"].contains(&x.as_str()) => return Some(false),
+            Err(e) => {
+                log::error!("readline error: {e}");
+                return None;
+            }
+            Ok(_) => (),
+        }
+    }
+}
+
+///
+/// Lists the files of `path` modified by the user, according to the manifest of `version`, and
+/// asks what to do with them. Without a manifest or modified files, the update is aborted on
+/// conflicts, as none are expected.
+///
+fn ask_conflict_policy(
+    rl: &mut DefaultEditor,
+    path: &Path,
+    version: &Version,
+) -> Option<(ConflictPolicy, Option<Conflicts>)> {
+    let conflicts = match Conflicts::find(path, version) {
+        Ok(Some(x)) if !x.files.is_empty() => x,
+        Ok(_) => return Some((ConflictPolicy::Abort, None)),
+        Err(e) => {
+            log::error!("cannot look for modified files: {e}");
+            return None;
+        }
+    };
+    println!("Les fichiers suivants ont été modifiés :");
+    for file in &conflicts.files {
+        println!("  - {}", file.display());
+    }
+    println!("Que souhaitez vous en faire ? [annuler/remplacer/ignorer]");
+    println!(
+        "  remplacer : les fichiers sont mis de côté dans .thl-patcher/conflicts, puis mis à jour"
+    );
+    println!("  ignorer : les fichiers sont conservés tels quels, sans être mis à jour");
+    loop {
+        match rl.readline("> ") {
+            Ok(x) if ["a", "annuler"].contains(&x.as_str()) => return None,
+            Ok(x) if ["r", "remplacer"].contains(&x.as_str()) => {
+                return Some((ConflictPolicy::Overwrite, Some(conflicts)));
+            }
+            Ok(x) if ["i", "ignorer"].contains(&x.as_str()) => {
+                return Some((ConflictPolicy::Skip, Some(conflicts)));
+            }
+            Err(e) => {
+                log::error!("readline error: {e}");
+                return None;
+            }
+            Ok(_) => (),
+        }
+    }
+}
+
+///
+/// Lets the user choose which of the `reachable` versions to install, or which of the `older`
+/// ones to go back to, by number or by name. An empty answer picks the latest one.
+///
+fn pick_target(
+    rl: &mut DefaultEditor,
+    source: &Source,
+    older: &[usize],
+    reachable: &[usize],
+) -> Option<usize> {
+    let latest = reachable.last().copied();
+    let versions: Vec<usize> = older.iter().chain(reachable).copied().collect();
+    println!("Versions disponibles :");
+    for (i, &version) in versions.iter().enumerate() {
+        let suffix = if Some(version) == latest {
+            " (dernière)"
+        } else if older.contains(&version) {
+            " (retour en arrière)"
+        } else {
+            ""
+        };
+        println!("  {}. {}{suffix}", i + 1, source.versions[version].name);
+    }
+    if latest.is_some() {
+        println!(
+            "Quelle version souhaitez vous installer ? Appuyez sur {} pour la dernière.",
+            "Entrée".bold()
+        );
+    } else {
+        println!("Quelle version souhaitez vous installer ?");
+    }
+    loop {
+        let answer = match rl.readline("> ") {
+            Ok(x) => x,
+            Err(e) => {
+                log::error!("readline error: {e}");
+                return None;
+            }
+        };
+        let answer = answer.trim();
+        if answer.is_empty()
+            && let Some(latest) = latest
+        {
+            return Some(latest);
+        }
+        if let Ok(i) = answer.parse::<usize>()
+            && let Some(&version) = i.checked_sub(1).and_then(|i| versions.get(i))
+        {
+            return Some(version);
+        }
+        if let Some(&version) = versions
+            .iter()
+            .find(|&&version| source.versions[version].name == answer)
+        {
+            return Some(version);
+        }
+        println!("Version inconnue, veuillez réessayer.");
+    }
+}
+
+///
+/// Prints the release notes of the versions about to be installed, and the size of the download
+///
+fn print_changes(versions: &[Version], transitions: &[VersionTransitionRef]) {
+    for version in versions {
+        let title = match version.formatted_release_date() {
+            Some(date) => format!("{} ({date})", version.name).bold(),
+            None => version.name.bold(),
+        };
+        match version.importance {
+            Some(Importance::Critical) => println!("{title} {}", "[critique]".red()),
+            Some(Importance::Recommended) => println!("{title} {}", "[recommandée]".yellow()),
+            Some(Importance::Optional) => println!("{title} [facultative]"),
+            None => println!("{title}"),
+        }
+        if let Some(changelog) = &version.changelog {
+            for line in changelog.lines() {
+                println!("    {line}");
+            }
+        }
+    }
+    let sizes: Option<u64> = transitions
+        .iter()
+        .map(|transition| transition.patch?.archive_size)
+        .sum();
+    if let Some(size) = sizes {
+        println!("Taille du téléchargement : {} Mo", size / 1_000_000);
+    }
+}
+
+fn fetch_source(config: &PatcherConfig) -> Option<Source> {
+    match Source::from_url(&config.source) {
+        Ok(s) => {
+            log::debug!("source fetched successfully");
+            Some(s)
+        }
+        Err(e) => {
+            log::error!("error while fetching source: {e}");
+            None
+        }
+    }
+}
+
+///
+/// Offers to replace the patcher with the newer release declared in the source, and relaunches
+/// it. Returns whether the patcher was replaced, in which case it should exit.
+///
+fn update_patcher(rl: &mut DefaultEditor, source: &Source) -> bool {
+    let Some(release) = &source.patcher else {
+        return false;
+    };
+    let Some(download) = release.update_for(EXECUTABLE, env!("CARGO_PKG_VERSION")) else {
+        return false;
+    };
+    println!(
+        "Une nouvelle version du patcheur ({}) est disponible. Souhaitez vous l'installer ? [oui/non]",
+        release.version
+    );
+    if confirm(rl) != Some(true) {
+        log::info!("refused patcher update");
+        return false;
+    }
+    let executable = match self_update(download, &CancellationToken::new()) {
+        Ok(x) => x,
+        Err(e) => {
+            log::error!("patcher update failed: {e}");
+            println!("La mise à jour du patcheur a échoué, la version actuelle va être utilisée.");
+            return false;
+        }
+    };
+    println!("Le patcheur a été mis à jour, redémarrage...");
+    match relaunch(&executable) {
+        Ok(()) => true,
+        Err(e) => {
+            log::error!("couldn't relaunch the patcher: {e}");
+            println!(
+                "Le patcheur a été mis à jour. Relancez-le pour utiliser la nouvelle version."
+            );
+            true
+        }
+    }
+}
+
+///
+/// Gets the version chain of the channel followed by the install directory `path`
+///
+fn select_channel(source: &Source, config: &PatcherConfig, path: &Path) -> Option<Source> {
+    let channel = config.channel_for(path);
+    match source.channel(channel.as_deref()) {
+        Ok(s) => {
+            if let Some(channel) = channel {
+                println!("Canal : {channel}");
+            }
+            Some(s)
+        }
+        Err(e) => {
+            log::error!("cannot follow channel: {e}");
+            None
+        }
+    }
+}
+
+fn channel(rl: &mut DefaultEditor, config: &PatcherConfig, source: &Source) -> ExitCode {
+    let Some(path) = ask_path(rl, config) else {
+        return ExitCode::FAILURE;
+    };
+    let path = Path::new(&path);
+
+    let current = config
+        .channel_for(path)
+        .unwrap_or_else(|| DEFAULT_CHANNEL.to_string());
+    println!("Canaux disponibles :");
+    for name in source.channel_names() {
+        let suffix = if name == current { " (actuel)" } else { "" };
+        println!("  - {name}{suffix}");
+    }
+    println!("Quel canal souhaitez vous suivre ?");
+    let answer = match rl.readline("> ") {
+        Ok(x) => x,
+        Err(e) => {
+            log::error!("readline error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let answer = answer.trim();
+    if !source.channel_names().any(|name| name == answer) {
+        println!("Canal inconnu : {answer}");
+        return ExitCode::FAILURE;
+    }
+
+    let mut settings = Settings::read(path);
+    settings.channel = Some(answer.to_string());
+    match settings.write(path) {
+        Ok(()) => {
+            println!("Ce dossier suit désormais le canal {answer}.");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            log::error!("couldn't write the settings: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+///
+/// Offers to roll back the last update of the main versions or of an installed component, when
+/// a backup of it was kept
+///
+fn rollback(rl: &mut DefaultEditor, config: &PatcherConfig, source: &Source) -> ExitCode {
+    let Some(path) = ask_path(rl, config) else {
+        return ExitCode::FAILURE;
+    };
+    let path = Path::new(&path);
+    let Some(source) = select_channel(source, config, path) else {
+        return ExitCode::FAILURE;
+    };
+
+    let mut chains = vec![source.clone()];
+    chains.extend(
+        source
+            .components
+            .iter()
+            .filter_map(|component| source.component(&component.name).ok()),
+    );
+    let mut backups = Vec::new();
+    for chain in chains {
+        match Backup::last(path, chain.component_name()) {
+            Ok(Some(backup)) => backups.push((backup, chain)),
+            Ok(None) => (),
+            Err(e) => {
+                log::error!("error while reading backups: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some((backup, chain)) = pick_backup(rl, backups) else {
+        return ExitCode::FAILURE;
+    };
+
+    let prefix = backup
+        .component
+        .as_ref()
+        .map(|component| format!("{component} : "))
+        .unwrap_or_default();
+    println!(
+        "{prefix}Souhaitez vous revenir de la version {} à la version {} ? [oui/non]",
+        backup.to, backup.from
+    );
+    match confirm(rl) {
+        Some(true) => (),
+        Some(false) => {
+            log::info!("refused rollback");
+            return ExitCode::FAILURE;
+        }
+        None => return ExitCode::FAILURE,
+    }
+
+    match patcher_common::backup::rollback(path, &chain) {
+        Ok(version) => {
+            println!(
+                "{prefix}Version actuelle : {}",
+                chain.versions[version].name
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            log::error!("rollback failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+///
+/// Lets the user choose which of the `backups` to restore, along with the version chain it
+/// belongs to. The only backup is picked without asking.
+///
+fn pick_backup(
+    rl: &mut DefaultEditor,
+    mut backups: Vec<(Backup, Source)>,
+) -> Option<(Backup, Source)> {
+    match backups.len() {
+        0 => {
+            println!("Aucune sauvegarde n'a été trouvée pour ce dossier.");
+            return None;
+        }
+        1 => return backups.pop(),
+        _ => (),
+    }
+    println!("Sauvegardes disponibles :");
+    for (i, (backup, _)) in backups.iter().enumerate() {
+        let component = backup.component.as_deref().unwrap_or("jeu");
+        println!(
+            "  {}. {component} : {} -> {}",
+            i + 1,
+            backup.to,
+            backup.from
+        );
+    }
+    println!("Quelle sauvegarde souhaitez vous restaurer ?");
+    loop {
+        let answer = match rl.readline("> ") {
+            Ok(x) => x,
+            Err(e) => {
+                log::error!("readline error: {e}");
+                return None;
+            }
+        };
+        if let Ok(i) = answer.trim().parse::<usize>()
+            && (1..=backups.len()).contains(&i)
+        {
+            return Some(backups.swap_remove(i - 1));
+        }
+        println!("Sauvegarde inconnue, veuillez réessayer.");
+    }
+}
+
+///
+/// Brings the install directory back to a clean copy of its detected version
+///
+fn restore(rl: &mut DefaultEditor, config: &PatcherConfig, source: &Source) -> ExitCode {
+    let Some(path) = ask_path(rl, config) else {
+        return ExitCode::FAILURE;
+    };
+    let path = Path::new(&path);
+    let Some(source) = select_channel(source, config, path) else {
+        return ExitCode::FAILURE;
+    };
+
+    let version = match source.get_current_version(path) {
+        Ok(Some(x)) => &source.versions[x],
+        Ok(None) => {
+            println!("Votre version n'a pas été reconnue, elle ne peut pas être restaurée.");
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            log::error!("error while fetching current version: {e}");
             return ExitCode::FAILURE;
         }
     };
+    println!(
+        "Souhaitez vous restaurer une copie propre de la version {} ? Les fichiers ajoutés ou modifiés seront déplacés dans .thl-patcher/conflicts. [oui/non]",
+        version.name
+    );
+    match confirm(rl) {
+        Some(true) => (),
+        Some(false) => {
+            log::info!("refused restoration");
+            return ExitCode::FAILURE;
+        }
+        None => return ExitCode::FAILURE,
+    }
+
+    let components = match source.installed_components(path) {
+        Ok(x) => x,
+        Err(e) => {
+            log::error!("error while detecting the components: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match patcher_common::manifest::restore_vanilla(
+        path,
+        version,
+        &components,
+        &CancellationToken::new(),
+    ) {
+        Ok(restoration) => {
+            for file in &restoration.removed {
+                println!("  {} {}", "supprimé".red(), file.display());
+            }
+            for file in &restoration.restored {
+                println!("  {} {}", "restauré".green(), file.display());
+            }
+            println!("La version {} a été restaurée.", version.name);
+            ExitCode::SUCCESS
+        }
+        Err(DownloadAndPatchError::NoManifest) => {
+            log::error!("version {} has no manifest", version.name);
+            println!("La version {} ne peut pas être restaurée.", version.name);
+            ExitCode::FAILURE
+        }
+        Err(e) => report_update_error(e),
+    }
+}
+
+///
+/// Lists the optional components of the source, and offers to update each installed one to its
+/// latest version
+///
+fn components(rl: &mut DefaultEditor, config: &PatcherConfig, source: &Source) -> ExitCode {
+    if source.components.is_empty() {
+        println!("Aucun composant optionnel n'est disponible.");
+        return ExitCode::SUCCESS;
+    }
+    let Some(path) = ask_path(rl, config) else {
+        return ExitCode::FAILURE;
+    };
     let path = Path::new(&path);
 
+    let mut code = ExitCode::SUCCESS;
+    for component in &source.components {
+        let Ok(chain) = source.component(&component.name) else {
+            continue;
+        };
+        match &component.description {
+            Some(description) => println!("{} ({description})", component.name.bold()),
+            None => println!("{}", component.name.bold()),
+        }
+        let current_version = match chain.get_current_version(path) {
+            Ok(Some(x)) => x,
+            Ok(None) => {
+                println!("  Non installé");
+                continue;
+            }
+            Err(e) => {
+                log::error!(
+                    "error while fetching the version of {}: {e}",
+                    component.name
+                );
+                code = ExitCode::FAILURE;
+                continue;
+            }
+        };
+        println!(
+            "  Version actuelle : {}",
+            chain.versions[current_version].name
+        );
+        let transitions = chain.plan_to_latest(current_version);
+        let Some(last) = transitions.last() else {
+            println!("  Ce composant est à jour.");
+            continue;
+        };
+        let target = chain
+            .find_version(&last.new.name)
+            .unwrap_or(current_version);
+        print_changes(chain.changes(current_version, target), &transitions);
+        println!(
+            "  Souhaitez vous installer la version {} ? [oui/non]",
+            last.new.name
+        );
+        match confirm(rl) {
+            Some(true) => (),
+            Some(false) => {
+                log::info!("refused update of {}", component.name);
+                continue;
+            }
+            None => return ExitCode::FAILURE,
+        }
+        let Some((conflict_policy, conflicts)) =
+            ask_conflict_policy(rl, path, &chain.versions[current_version])
+        else {
+            log::info!("update of {} cancelled", component.name);
+            code = ExitCode::FAILURE;
+            continue;
+        };
+        let progress = Progress::new(transitions.len() as u64);
+        if let Err(e) = patcher_common::download::download_and_patch(
+            path,
+            transitions.into_iter(),
+            &PatchOptions {
+                keep_backups: config.keep_backups,
+                conflict_policy,
+                conflicts,
+            },
+            &CancellationToken::new(),
+            progress,
+        ) {
+            code = report_update_error(e);
+        }
+    }
+    code
+}
+
+fn history(rl: &mut DefaultEditor, config: &PatcherConfig) -> ExitCode {
+    let Some(path) = ask_path(rl, config) else {
+        return ExitCode::FAILURE;
+    };
+
+    let entries = match patcher_common::history::read(Path::new(&path)) {
+        Ok(x) => x,
+        Err(e) => {
+            log::error!("error while reading history: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if entries.is_empty() {
+        println!("Aucune mise à jour n'a encore été effectuée sur ce dossier.");
+    }
+    for entry in entries {
+        let outcome = match entry.outcome {
+            Outcome::Success => "réussie".green(),
+            Outcome::Cancelled => "annulée".yellow(),
+            Outcome::Failed => "échouée".red(),
+        };
+        let mut transition = if entry.is_full_install() {
+            format!("installation complète de {}", entry.to)
+        } else if entry.is_restoration() {
+            format!("restauration de {}", entry.to)
+        } else {
+            format!("{} -> {}", entry.from, entry.to)
+        };
+        if let Some(component) = &entry.component {
+            transition = format!("{component} : {transition}");
+        }
+        println!(
+            "[{}] {transition} : {outcome} ({} fichier(s))",
+            entry.formatted_timestamp(),
+            entry.files.len()
+        );
+        if let Some(error) = entry.error {
+            println!("    {error}");
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+fn update(rl: &mut DefaultEditor, config: &PatcherConfig, source: &Source) -> ExitCode {
+    let Some(path) = ask_path(rl, config) else {
+        return ExitCode::FAILURE;
+    };
+    let path = Path::new(&path);
+    let Some(source) = select_channel(source, config, path) else {
+        return ExitCode::FAILURE;
+    };
+
     let current_version = match source.get_current_version(path) {
         Ok(Some(x)) => {
             log::debug!("found version {x}");
             x
         }
-        Ok(None) => {
-            log::error!("unknown or corrupted version");
-            return ExitCode::FAILURE;
-        }
+        Ok(None) => return full_install(rl, &source, path),
         Err(e) => {
             log::error!("error while fetching current version: {e}");
             return ExitCode::FAILURE;
@@ -123,44 +732,159 @@ fn inner(rl: &mut DefaultEditor) -> ExitCode {
         "Version actuelle : {}",
         source.versions[current_version].name
     );
-    let versions_to_install = source.get_transitions(current_version);
     println!(
         "Dernière version : {}",
         source.versions.last().unwrap().name
     );
-    if current_version + 1 == source.versions.len() {
+    let latest = current_version + 1 == source.versions.len();
+    if latest {
         println!("Vous avez déjà la dernière version !");
+    }
+    let reachable = source.reachable_versions(current_version);
+    let older = source.reachable_older_versions(current_version);
+    if reachable.is_empty() && older.is_empty() {
+        if !latest {
+            println!("Aucune mise à jour n'est disponible depuis votre version.");
+        }
         return ExitCode::SUCCESS;
     }
-    println!("Souhaitez vous installer la dernière version ? [oui/non]");
 
-    let should_download = loop {
-        match rl.readline("> ") {
-            Ok(x) if ["y", "yes", "o", "oui"].contains(&x.as_str()) => break true,
-            Ok(x) if ["n", "no", "non"].contains(&x.as_str()) => break false,
-            Err(e) => {
-                log::error!("readline error: {e}");
-                return ExitCode::FAILURE;
-            }
-            Ok(_) => (),
+    let Some(target) = pick_target(rl, &source, &older, &reachable) else {
+        return ExitCode::FAILURE;
+    };
+    let versions_to_install = match source.plan_to(current_version, &source.versions[target].name) {
+        Ok(x) => x,
+        Err(e) => {
+            log::error!("cannot plan the update: {e}");
+            return ExitCode::FAILURE;
         }
     };
+    print_changes(
+        source.changes(current_version, target),
+        &versions_to_install,
+    );
+    println!(
+        "Souhaitez vous installer la version {} ? [oui/non]",
+        source.versions[target].name
+    );
+
+    let Some(should_download) = confirm(rl) else {
+        return ExitCode::FAILURE;
+    };
 
     if !should_download {
         log::info!("refused download");
         return ExitCode::FAILURE;
     }
+    let Some((conflict_policy, conflicts)) =
+        ask_conflict_policy(rl, path, &source.versions[current_version])
+    else {
+        log::info!("update cancelled");
+        return ExitCode::FAILURE;
+    };
 
     let progress = Progress::new(versions_to_install.len() as u64);
-    match patcher_common::download::download_and_patch(path, versions_to_install, progress) {
+    match patcher_common::download::download_and_patch(
+        path,
+        versions_to_install.into_iter(),
+        &PatchOptions {
+            keep_backups: config.keep_backups,
+            conflict_policy,
+            conflicts,
+        },
+        &CancellationToken::new(),
+        progress,
+    ) {
         Ok(()) => {
             log::debug!("update successfully applied");
             ExitCode::SUCCESS
         }
-        Err(e) => {
+        Err(e) => report_update_error(e),
+    }
+}
+
+fn report_update_error(error: DownloadAndPatchError) -> ExitCode {
+    match error {
+        DownloadAndPatchError::NotEnoughSpace {
+            path,
+            required,
+            available,
+        } => {
+            log::error!("not enough space on `{}`", path.display());
+            println!(
+                "Espace disque insuffisant sur le volume de {} : {} Mo nécessaires, {} Mo disponibles.",
+                path.display(),
+                required / 1_000_000,
+                available / 1_000_000
+            );
+            ExitCode::FAILURE
+        }
+        e @ DownloadAndPatchError::Locked { .. } => {
             log::error!("update failed: {e}");
+            println!("Un autre patcheur est déjà en train de mettre à jour ce dossier.");
+            ExitCode::FAILURE
+        }
+        DownloadAndPatchError::BlockedFiles(files) => {
+            log::error!("{} file(s) cannot be written", files.len());
+            println!(
+                "Les fichiers suivants ne peuvent pas être modifiés. Vérifiez que le jeu est bien fermé."
+            );
+            for file in files {
+                println!("  - {} ({})", file.path.display(), file.error);
+            }
             ExitCode::FAILURE
         }
+        DownloadAndPatchError::Conflicts(files) => {
+            log::error!("{} file(s) were modified", files.len());
+            println!("Les fichiers suivants ont été modifiés depuis l'installation :");
+            for file in files {
+                println!("  - {}", file.display());
+            }
+            ExitCode::FAILURE
+        }
+        e => {
+            log::error!("update failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+///
+/// Offers to install a clean copy of the most recent version having a full archive, when no
+/// version was detected in `path`
+///
+fn full_install(rl: &mut DefaultEditor, source: &Source, path: &Path) -> ExitCode {
+    let Some(version) = source.latest_full_install() else {
+        log::error!("unknown or corrupted version");
+        return ExitCode::FAILURE;
+    };
+    let version = &source.versions[version];
+    println!("Votre version n'a pas été reconnue.");
+    println!(
+        "Souhaitez vous télécharger une copie complète de la version {} ? [oui/non]",
+        version.name
+    );
+    match confirm(rl) {
+        Some(true) => (),
+        Some(false) => {
+            log::info!("refused full install");
+            return ExitCode::FAILURE;
+        }
+        None => return ExitCode::FAILURE,
+    }
+
+    match patcher_common::download::download_full_install(
+        path,
+        version,
+        &CancellationToken::new(),
+        Progress::new(1),
+    ) {
+        Ok(()) => {
+            log::debug!("full install successfully applied");
+            println!("Version actuelle : {}", version.name);
+            ExitCode::SUCCESS
+        }
+        Err(e) => report_update_error(e),
     }
 }
 
@@ -175,7 +899,31 @@ fn main() -> ExitCode {
         }
     };
 
-    let code = inner(&mut rl);
+    let config = get_config();
+    remove_previous_executable();
+    // The history doesn't need the source, so errors fetching it are only reported by the
+    // commands needing it
+    let source = fetch_source(&config);
+    if let Some(source) = &source
+        && update_patcher(&mut rl, source)
+    {
+        return ExitCode::SUCCESS;
+    }
+    let code = match (Command::from_args(), &source) {
+        (Some(Command::History), _) => history(&mut rl, &config),
+        (Some(_), None) => ExitCode::FAILURE,
+        (Some(Command::Update), Some(source)) => update(&mut rl, &config, source),
+        (Some(Command::Rollback), Some(source)) => rollback(&mut rl, &config, source),
+        (Some(Command::Channel), Some(source)) => channel(&mut rl, &config, source),
+        (Some(Command::Restore), Some(source)) => restore(&mut rl, &config, source),
+        (Some(Command::Components), Some(source)) => components(&mut rl, &config, source),
+        (None, _) => {
+            println!(
+                "Usage : patcher-terminal [update|history|rollback|channel|restore|components]"
+            );
+            ExitCode::FAILURE
+        }
+    };
 
     if code == ExitCode::FAILURE {
         println!(